@@ -0,0 +1,130 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use timer_ship::{
+    core::{QueueKind, Timer, TimerQueue},
+    utils::clock::current_time_ms,
+};
+
+const KINDS: [QueueKind; 2] = [QueueKind::BinaryHeap, QueueKind::TimingWheel];
+const SIZES: [usize; 2] = [1_000, 10_000];
+
+/// Session-like timers spread over the next 30 minutes
+fn session_timers(count: usize) -> Vec<Timer> {
+    let now = current_time_ms();
+    (0..count)
+        .map(|i| Timer::new_at(now + 60_000 + (i as u64 * 7_919) % 1_800_000, now))
+        .collect()
+}
+
+/// Filled queue plus the timers in it, for benchmarks that start from a full queue
+fn filled(kind: QueueKind, timers: &[Timer]) -> (Box<dyn TimerQueue>, Vec<Timer>) {
+    let mut queue = kind.build(current_time_ms());
+    for timer in timers {
+        queue.add_timer(timer.clone());
+    }
+    (queue, timers.to_vec())
+}
+
+fn benchmark_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue_insert");
+    for size in SIZES {
+        let timers = session_timers(size);
+        for kind in KINDS {
+            group.bench_with_input(BenchmarkId::new(format!("{:?}", kind), size), &timers, |b, timers| {
+                b.iter_batched(
+                    || (kind.build(current_time_ms()), timers.clone()),
+                    |(mut queue, timers)| {
+                        for timer in timers {
+                            queue.add_timer(timer);
+                        }
+                        black_box(queue)
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn benchmark_insert_cancel(c: &mut Criterion) {
+    // Most session timers are cancelled before they expire
+    let mut group = c.benchmark_group("queue_cancel_90_percent");
+    group.sample_size(10);
+    for size in SIZES {
+        let timers = session_timers(size);
+        for kind in KINDS {
+            group.bench_with_input(BenchmarkId::new(format!("{:?}", kind), size), &timers, |b, timers| {
+                b.iter_batched(
+                    || filled(kind, timers),
+                    |(mut queue, timers)| {
+                        for timer in timers.iter().filter(|timer| timer.id.as_u128() % 10 != 0) {
+                            queue.remove_timer(timer.id);
+                        }
+                        black_box(queue)
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn benchmark_expire(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue_expire_all");
+    for size in SIZES {
+        let timers = session_timers(size);
+        for kind in KINDS {
+            group.bench_with_input(BenchmarkId::new(format!("{:?}", kind), size), &timers, |b, timers| {
+                b.iter_batched(
+                    || filled(kind, timers).0,
+                    |mut queue| {
+                        while let Some(timer) = queue.pop_timer() {
+                            black_box(timer);
+                        }
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn benchmark_mixed(c: &mut Criterion) {
+    // Insert, cancel nine in ten, and expire the rest through peek and pop like the timer thread
+    let mut group = c.benchmark_group("queue_insert_cancel_expire");
+    group.sample_size(10);
+    for size in SIZES {
+        let timers = session_timers(size);
+        for kind in KINDS {
+            group.bench_with_input(BenchmarkId::new(format!("{:?}", kind), size), &timers, |b, timers| {
+                b.iter_batched(
+                    || (kind.build(current_time_ms()), timers.clone()),
+                    |(mut queue, timers)| {
+                        for (i, timer) in timers.iter().enumerate() {
+                            queue.add_timer(timer.clone());
+                            if i % 10 != 0 {
+                                queue.remove_timer(timer.id);
+                            }
+                        }
+                        while queue.peek_timer().is_some() {
+                            black_box(queue.pop_timer());
+                        }
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    benchmark_insert,
+    benchmark_insert_cancel,
+    benchmark_expire,
+    benchmark_mixed
+);
+criterion_main!(benches);
@@ -1,9 +1,26 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use timer_ship::TimerShip;
-use std::sync::Arc;
+use timer_ship::{
+    core::TimerState,
+    persistence::RecoveryManager,
+    LogEntry, LogOperation, OpLog, RecoveryPolicy, TimerShip, TimerStore,
+};
+use std::{path::PathBuf, sync::Arc};
+
+/// Creates an empty directory under the system temp dir to hold one benchmark's log
+fn bench_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("timer_ship_{}_{}", name, timer_ship::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).expect("Failed to create benchmark directory");
+    dir
+}
+
+/// Path of the log file inside a benchmark directory
+fn bench_log(dir: &std::path::Path) -> String {
+    dir.join("timers.log").to_str().expect("temp dir is valid UTF-8").to_string()
+}
 
 fn benchmark_timer_creation(c: &mut Criterion) {
-    let timer_ship = TimerShip::new("bench_test.log").expect("Failed to create TimerShip");
+    let dir = bench_dir("bench_creation");
+    let timer_ship = TimerShip::new(&bench_log(&dir)).expect("Failed to create TimerShip");
     
     c.bench_function("timer_creation", |b| {
         let mut counter = 0;
@@ -18,10 +35,15 @@ fn benchmark_timer_creation(c: &mut Criterion) {
             ).unwrap();
         })
     });
+
+    timer_ship.shutdown();
+    drop(timer_ship);
+    let _ = std::fs::remove_dir_all(&dir);
 }
 
 fn benchmark_timer_listing(c: &mut Criterion) {
-    let timer_ship = TimerShip::new("bench_list_test.log").expect("Failed to create TimerShip");
+    let dir = bench_dir("bench_listing");
+    let timer_ship = TimerShip::new(&bench_log(&dir)).expect("Failed to create TimerShip");
     
     // Pre-populate with long-duration timers to ensure they stay active
     for i in 0..1000 {
@@ -35,10 +57,15 @@ fn benchmark_timer_listing(c: &mut Criterion) {
             black_box(timer_ship.list_active_timers());
         })
     });
+
+    timer_ship.shutdown();
+    drop(timer_ship);
+    let _ = std::fs::remove_dir_all(&dir);
 }
 
 fn benchmark_create_remove_cycle(c: &mut Criterion) {
-    let timer_ship = TimerShip::new("bench_cycle_test.log").expect("Failed to create TimerShip");
+    let dir = bench_dir("bench_cycle");
+    let timer_ship = TimerShip::new(&bench_log(&dir)).expect("Failed to create TimerShip");
     
     c.bench_function("create_remove_cycle", |b| {
         let mut counter = 0;
@@ -53,13 +80,19 @@ fn benchmark_create_remove_cycle(c: &mut Criterion) {
             black_box(timer_ship.remove_timer(timer_id).unwrap());
         })
     });
+
+    timer_ship.shutdown();
+    drop(timer_ship);
+    let _ = std::fs::remove_dir_all(&dir);
 }
 
 fn benchmark_concurrent_operations(c: &mut Criterion) {
+    let dir = bench_dir("bench_concurrent");
+    let log_path = bench_log(&dir);
     c.bench_function("concurrent_timer_creation", |b| {
         b.iter(|| {
             let timer_ship = Arc::new(
-                TimerShip::new("bench_concurrent_test.log").expect("Failed to create TimerShip")
+                TimerShip::new(&log_path).expect("Failed to create TimerShip")
             );
             
             let handles: Vec<_> = (0..4).map(|thread_id| {
@@ -79,32 +112,89 @@ fn benchmark_concurrent_operations(c: &mut Criterion) {
             }
             
             black_box(timer_ship.active_timer_count());
+            timer_ship.shutdown();
         })
     });
+    let _ = std::fs::remove_dir_all(&dir);
 }
 
 fn benchmark_recovery(c: &mut Criterion) {
     // Pre-create a log file with many operations using long durations
+    let dir = bench_dir("bench_recovery");
+    let log_path = bench_log(&dir);
     {
-        let timer_ship = TimerShip::new("bench_recovery_test.log").expect("Failed to create TimerShip");
+        let timer_ship = TimerShip::new(&log_path).expect("Failed to create TimerShip");
         for i in 0..500 {
             let duration_str = format!("{}h", (i % 24) + 1); // Long durations
             let data = format!("Recovery benchmark timer #{}", i);
             let _ = timer_ship.set_timer_with_duration(&duration_str, data);
         }
-    } // TimerShip goes out of scope
+        timer_ship.shutdown();
+    } // TimerShip goes out of scope, releasing the log
     
     c.bench_function("recovery_from_log", |b| {
         b.iter(|| {
-            black_box(
-                TimerShip::new("bench_recovery_test.log").expect("Failed to create TimerShip")
-            );
+            let timer_ship = TimerShip::new(&log_path).expect("Failed to create TimerShip");
+            timer_ship.shutdown();
+            black_box(timer_ship);
         })
     });
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+// Compare recovery of a segmented log with plain vs gzip-compressed sealed segments
+fn benchmark_recovery_compression(c: &mut Criterion) {
+    let mut group = c.benchmark_group("recovery_compression");
+
+    for compressed in [false, true] {
+        let dir = bench_dir(&format!("bench_recovery_segments_{}", compressed));
+        let log = OpLog::segmented(dir.to_str().unwrap(), 64 * 1024)
+            .expect("Failed to create OpLog")
+            .with_compression(compressed);
+
+        for i in 0..5000u64 {
+            let entry = LogEntry::new(
+                i,
+                LogOperation::SetTimer {
+                    timer_id: timer_ship::Uuid::new_v4(),
+                    expires_at: u64::MAX,
+                    data: format!("session:user{}@example.com", i % 100),
+                    created_at: 0,
+                    duration_ms: u64::MAX,
+                    priority: 0,
+                    uptime: false,
+                    key: None,
+                },
+            );
+            log.append_log(entry).expect("Failed to append");
+        }
+        if compressed {
+            log.compress_sealed_segments().expect("Failed to compress segments");
+        }
+
+        let name = if compressed { "compressed" } else { "uncompressed" };
+        let store: Arc<dyn TimerStore> = Arc::new(log);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                black_box(
+                    RecoveryManager::new(store.clone())
+                        .recover_from_logs(&mut TimerState::default(), RecoveryPolicy::Lenient)
+                        .expect("Failed to recover"),
+                );
+            })
+        });
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    group.finish();
 }
 
 // Add a benchmark specifically for very short timers (expiration testing)
 fn benchmark_short_timer_expiration(c: &mut Criterion) {
+    let dir = bench_dir("bench_expiration");
+    let log_path = bench_log(&dir);
     c.bench_function("short_timer_expiration", |b| {
         b.iter(|| {
             let expired_count = Arc::new(std::sync::Mutex::new(0));
@@ -115,7 +205,7 @@ fn benchmark_short_timer_expiration(c: &mut Criterion) {
                 *count += 1;
             });
             
-            let timer_ship = TimerShip::with_callback("bench_expiration_test.log", Some(callback))
+            let timer_ship = TimerShip::with_callback(&log_path, Some(callback))
                 .expect("Failed to create TimerShip");
             
             // Create 10 very short timers
@@ -128,9 +218,11 @@ fn benchmark_short_timer_expiration(c: &mut Criterion) {
             // Wait for all to expire
             std::thread::sleep(std::time::Duration::from_millis(200));
             
+            timer_ship.shutdown();
             black_box(*expired_count.lock().unwrap());
         })
     });
+    let _ = std::fs::remove_dir_all(&dir);
 }
 
 criterion_group!(
@@ -140,6 +232,7 @@ criterion_group!(
     benchmark_create_remove_cycle,
     benchmark_concurrent_operations,
     benchmark_recovery,
+    benchmark_recovery_compression,
     benchmark_short_timer_expiration
 );
 criterion_main!(benches);
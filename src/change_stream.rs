@@ -0,0 +1,130 @@
+//! Change-data-capture: streaming set, remove and fire records by sequence number.
+
+use crate::persistence::{LogEntry, TimerStore};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Wakes change streams when entries are appended
+#[derive(Debug, Default)]
+pub(crate) struct ChangeNotifier {
+    state: Mutex<NotifierState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct NotifierState {
+    /// Highest sequence number appended through this instance
+    last_seq: u64,
+    /// Set once the owning `TimerShip` shuts down
+    closed: bool,
+}
+
+impl ChangeNotifier {
+    /// Records that `seq` was appended and wakes every waiting stream
+    pub(crate) fn notify(&self, seq: u64) {
+        let mut state = self.state.lock().expect("Failed to lock change notifier");
+        state.last_seq = state.last_seq.max(seq);
+        self.changed.notify_all();
+    }
+
+    /// Ends every stream once it has caught up
+    pub(crate) fn close(&self) {
+        self.state.lock().expect("Failed to lock change notifier").closed = true;
+        self.changed.notify_all();
+    }
+
+    /// Waits until an entry after `seq` is appended, the notifier is closed, or
+    /// `deadline` passes. Returns whether anything after `seq` is known to exist.
+    fn wait_beyond(&self, seq: u64, deadline: Option<Instant>) -> bool {
+        let mut state = self.state.lock().expect("Failed to lock change notifier");
+        while state.last_seq <= seq && !state.closed {
+            state = match deadline {
+                None => self.changed.wait(state).expect("Failed to lock change notifier"),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    self.changed
+                        .wait_timeout(state, deadline - now)
+                        .expect("Failed to lock change notifier")
+                        .0
+                }
+            };
+        }
+        state.last_seq > seq
+    }
+
+    /// Highest sequence number appended so far, and whether the notifier is closed
+    fn snapshot(&self) -> (u64, bool) {
+        let state = self.state.lock().expect("Failed to lock change notifier");
+        (state.last_seq, state.closed)
+    }
+}
+
+/// Blocking iterator over the changes made to a `TimerShip`, created by `TimerShip::watch`.
+///
+/// Yields every retained entry after the starting sequence number, then waits
+/// for new ones. Iteration ends once the `TimerShip` is shut down and the
+/// stream has caught up.
+#[derive(Debug)]
+pub struct ChangeStream {
+    store: Arc<dyn TimerStore>,
+    notifier: Arc<ChangeNotifier>,
+    cursor: u64,
+    pending: VecDeque<LogEntry>,
+}
+
+impl ChangeStream {
+    pub(crate) fn new(store: Arc<dyn TimerStore>, notifier: Arc<ChangeNotifier>, from_seq: u64) -> Self {
+        ChangeStream {
+            store,
+            notifier,
+            cursor: from_seq,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Sequence number of the last entry returned, to resume from later with `watch`
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Waits up to `timeout` for the next change, returning `None` if there was none
+    pub fn next_timeout(&mut self, timeout: Duration) -> std::io::Result<Option<LogEntry>> {
+        self.next_before(Some(Instant::now() + timeout))
+    }
+
+    fn next_before(&mut self, deadline: Option<Instant>) -> std::io::Result<Option<LogEntry>> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                self.cursor = entry.seq;
+                return Ok(Some(entry));
+            }
+
+            // Anything appended up to `seen` is covered by the read below, even if
+            // compaction has since dropped it, so only wait for what comes after
+            let (seen, closed) = self.notifier.snapshot();
+            self.pending.extend(self.store.changes_since(self.cursor)?);
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if closed || timed_out || !self.notifier.wait_beyond(self.cursor.max(seen), deadline) {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl Iterator for ChangeStream {
+    type Item = std::io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_before(None).transpose()
+    }
+}
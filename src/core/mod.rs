@@ -1,7 +1,9 @@
 pub mod timer;
-pub mod timer_data;
 pub mod timer_queue;
+pub mod timer_state;
+pub mod timing_wheel;
 
-pub use timer::Timer;
-pub use timer_data::TimerData;
-pub use timer_queue::Timers;
+pub use timer::{FireOrder, Timer};
+pub use timer_queue::{QueueKind, TimerQueue, Timers};
+pub use timer_state::TimerState;
+pub use timing_wheel::TimingWheel;
@@ -1,3 +1,5 @@
+use crate::utils::clock::current_time_ms;
+use std::cmp::Reverse;
 use uuid::Uuid;
 
 /// Represents a single timer with expiration time and unique ID
@@ -5,20 +7,98 @@ use uuid::Uuid;
 pub struct Timer {
     pub expires_at: u64, // milliseconds since UNIX epoch
     pub id: Uuid,
+    pub created_at: u64, // milliseconds since UNIX epoch
+    /// Duration the timer was originally set for; reschedules and deadline
+    /// shifts leave it unchanged
+    pub duration_ms: u64,
+    /// Among timers due in the same millisecond, higher priorities fire first
+    pub priority: u8,
+    /// Sequence number of the log record that last scheduled the timer, so
+    /// equal deadlines and priorities fire in the order they were scheduled
+    pub seq: u64,
+    /// Counts only time while a `TimerShip` is running: recovery moves the
+    /// deadline forward by however long the process was down
+    pub uptime: bool,
+    /// Groups timers for `MissedTimerPolicy::FireLatestPerKey`
+    pub key: Option<String>,
 }
 
+/// Sort key putting timers in the order they fire
+pub type FireOrder = (u64, Reverse<u8>, u64, Uuid);
+
 impl Timer {
-    /// Creates a new timer with the given expiration time in milliseconds
+    /// Creates a new timer, created now, with the given expiration time in milliseconds
     pub fn new(expires_at: u64) -> Self {
+        Self::new_at(expires_at, current_time_ms())
+    }
+
+    /// Creates a new timer set at `created_at` that expires at `expires_at`,
+    /// both in milliseconds since UNIX epoch as read from the caller's clock
+    pub fn new_at(expires_at: u64, created_at: u64) -> Self {
+        Self::with_id_at(expires_at, Uuid::new_v4(), created_at)
+    }
+
+    /// Creates a timer with a specific ID (used for recovery)
+    pub fn with_id(expires_at: u64, id: Uuid) -> Self {
+        Self::with_id_at(expires_at, id, current_time_ms())
+    }
+
+    /// Creates a timer with a specific ID, set at `created_at`
+    pub fn with_id_at(expires_at: u64, id: Uuid, created_at: u64) -> Self {
         Timer {
             expires_at,
-            id: Uuid::new_v4(),
+            id,
+            created_at,
+            duration_ms: expires_at.saturating_sub(created_at),
+            priority: 0,
+            seq: 0,
+            uptime: false,
+            key: None,
         }
     }
 
-    /// Creates a timer with a specific ID (used for recovery)
-    pub fn with_id(expires_at: u64, id: Uuid) -> Self {
-        Timer { expires_at, id }
+    /// Sets when the timer was created, e.g. as recorded in the log, and
+    /// measures its duration from then
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.created_at = created_at;
+        self.duration_ms = self.expires_at.saturating_sub(created_at);
+        self
+    }
+
+    /// Sets the duration the timer was originally set for, e.g. as recorded in the log
+    pub fn with_duration_ms(mut self, duration_ms: u64) -> Self {
+        self.duration_ms = duration_ms;
+        self
+    }
+
+    /// Sets the timer's priority among timers due in the same millisecond
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets whether the timer counts only uptime
+    pub fn with_uptime(mut self, uptime: bool) -> Self {
+        self.uptime = uptime;
+        self
+    }
+
+    /// Sets the key grouping the timer with others of the same kind
+    pub fn with_key(mut self, key: Option<String>) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Sets the sequence number of the record that scheduled the timer
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// Orders timers by expiration, then highest priority, then scheduling
+    /// order; the ID breaks ties between records without sequence numbers
+    pub fn fire_order(&self) -> FireOrder {
+        (self.expires_at, Reverse(self.priority), self.seq, self.id)
     }
 
     /// Checks if the timer has expired
@@ -28,10 +108,6 @@ impl Timer {
 
     /// Gets the time left until expiration in milliseconds
     pub fn get_time_left(&self, current_time: u64) -> u64 {
-        if self.expires_at > current_time {
-            self.expires_at - current_time
-        } else {
-            0
-        }
+        self.expires_at.saturating_sub(current_time)
     }
 }
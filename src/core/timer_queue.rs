@@ -1,18 +1,24 @@
-use super::Timer;
+use super::{Timer, TimingWheel};
 use std::{
-    collections::BinaryHeap,
+    collections::{BinaryHeap, HashMap},
     cmp::Ordering,
-    sync::{Arc, Mutex},
 };
 use uuid::Uuid;
 
+/// Heap length below which cancelled entries are never swept out eagerly
+const MIN_SWEEP_LEN: usize = 64;
+
 /// Wrapper for Timer to implement reverse ordering for min-heap behavior
 #[derive(Debug, Clone)]
-struct TimerHeapItem(Timer);
+struct TimerHeapItem {
+    timer: Timer,
+    /// Matches the timer's entry in `HeapState::live` until the timer is removed or replaced
+    generation: u64,
+}
 
 impl PartialEq for TimerHeapItem {
     fn eq(&self, other: &Self) -> bool {
-        self.0.expires_at == other.0.expires_at
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -27,73 +33,198 @@ impl PartialOrd for TimerHeapItem {
 impl Ord for TimerHeapItem {
     fn cmp(&self, other: &Self) -> Ordering {
         // Reverse ordering to make BinaryHeap behave as min-heap
-        other.0.expires_at.cmp(&self.0.expires_at)
+        other
+            .timer
+            .fire_order()
+            .cmp(&self.timer.fire_order())
+            .then(other.generation.cmp(&self.generation))
     }
 }
 
-/// Container for managing multiple timers using a min-heap
-#[derive(Debug, Clone)]
+/// Ordered collection of pending timers.
+///
+/// Queues are not synchronized themselves; `TimerState` guards one together
+/// with the timers' data under a single lock.
+pub trait TimerQueue: Send + std::fmt::Debug {
+    /// Adds a timer, replacing any queued timer with the same ID
+    fn add_timer(&mut self, timer: Timer);
+
+    /// Gets the timer that expires first without removing it
+    fn peek_timer(&mut self) -> Option<Timer>;
+
+    /// Removes the timer with the given ID, if present
+    fn remove_timer(&mut self, timer_id: Uuid);
+
+    /// Gets all timers, in no particular order
+    fn get_all_timers(&self) -> Vec<Timer>;
+
+    /// Gets the count of timers
+    fn timer_count(&self) -> usize;
+
+    /// Pops the next timer to expire (removes and returns it)
+    fn pop_timer(&mut self) -> Option<Timer>;
+
+    /// Checks if the timer queue is empty
+    fn is_empty(&self) -> bool {
+        self.timer_count() == 0
+    }
+
+    /// Removes every timer
+    fn clear(&mut self);
+}
+
+/// Data structure backing a `TimerShip`'s pending timers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueKind {
+    /// Binary heap: O(log n) insert, O(1) cancel with lazy cleanup
+    #[default]
+    BinaryHeap,
+    /// Hierarchical timing wheel: O(1) insert and cancel
+    TimingWheel,
+}
+
+impl QueueKind {
+    /// Creates an empty queue of this kind, as of `now_ms` on the caller's clock
+    pub fn build(self, now_ms: u64) -> Box<dyn TimerQueue> {
+        match self {
+            QueueKind::BinaryHeap => Box::new(Timers::new()),
+            QueueKind::TimingWheel => Box::new(TimingWheel::new(now_ms)),
+        }
+    }
+}
+
+/// Container for managing multiple timers using a min-heap.
+///
+/// Removal only drops the timer from an index, leaving a tombstone in the
+/// heap; tombstones are discarded when they reach the top, and swept out once
+/// they outnumber live timers.
+#[derive(Debug, Default)]
 pub struct Timers {
-    timers: Arc<Mutex<BinaryHeap<TimerHeapItem>>>,
+    heap: BinaryHeap<TimerHeapItem>,
+    /// Generation of the heap item holding each live timer
+    live: HashMap<Uuid, u64>,
+    next_generation: u64,
 }
 
 impl Timers {
     pub fn new() -> Self {
-        Timers {
-            timers: Arc::new(Mutex::new(BinaryHeap::new())),
+        Self::default()
+    }
+
+    fn is_live(&self, item: &TimerHeapItem) -> bool {
+        self.live.get(&item.timer.id) == Some(&item.generation)
+    }
+
+    /// Drops tombstones from the top of the heap so it holds the next live timer
+    fn discard_cancelled(&mut self) {
+        while let Some(top) = self.heap.peek() {
+            if self.is_live(top) {
+                break;
+            }
+            self.heap.pop();
         }
     }
 
-    pub fn add_timer(&self, timer: Timer) {
-        let mut local_timers = self.timers.lock().expect("Failed to lock mutex");
-        local_timers.push(TimerHeapItem(timer));
-        drop(local_timers);
+    /// Rebuilds the heap without tombstones once they make up most of it
+    fn sweep_if_needed(&mut self) {
+        if self.heap.len() > MIN_SWEEP_LEN && self.heap.len() > 2 * self.live.len() {
+            let heap = std::mem::take(&mut self.heap);
+            self.heap = heap.into_iter().filter(|item| self.is_live(item)).collect();
+        }
     }
+}
 
-    pub fn peek_timer(&self) -> Option<Timer> {
-        let local_timers = self.timers.lock().expect("Failed to lock mutex");
-        local_timers.peek().map(|item| item.0.clone())
+impl TimerQueue for Timers {
+    fn add_timer(&mut self, timer: Timer) {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        if self.live.insert(timer.id, generation).is_some() {
+            self.sweep_if_needed();
+        }
+        self.heap.push(TimerHeapItem { timer, generation });
     }
 
-    pub fn remove_timer(&self, timer_id: Uuid) {
-        let mut local_timers = self.timers.lock().expect("Failed to lock mutex");
-        
-        // Convert heap to vector, remove the timer, and rebuild heap
-        let mut timers_vec: Vec<TimerHeapItem> = local_timers.drain().collect();
-        timers_vec.retain(|item| item.0.id != timer_id);
-        
-        // Rebuild the heap from the filtered vector
-        *local_timers = timers_vec.into_iter().collect();
-        drop(local_timers);
+    fn peek_timer(&mut self) -> Option<Timer> {
+        self.discard_cancelled();
+        self.heap.peek().map(|item| item.timer.clone())
     }
 
-    /// Gets all timers (clone of the internal heap as vector)
-    pub fn get_all_timers(&self) -> Vec<Timer> {
-        let local_timers = self.timers.lock().expect("Failed to lock mutex");
-        local_timers.iter().map(|item| item.0.clone()).collect()
+    /// Removes a timer in O(1), leaving its heap entry to be discarded later
+    fn remove_timer(&mut self, timer_id: Uuid) {
+        if self.live.remove(&timer_id).is_some() {
+            self.sweep_if_needed();
+        }
     }
-    
-    /// Gets the count of timers
-    pub fn timer_count(&self) -> usize {
-        let local_timers = self.timers.lock().expect("Failed to lock mutex");
-        local_timers.len()
+
+    /// Gets all timers, in heap order
+    fn get_all_timers(&self) -> Vec<Timer> {
+        self.heap
+            .iter()
+            .filter(|item| self.is_live(item))
+            .map(|item| item.timer.clone())
+            .collect()
     }
 
-    /// Pops the next timer to expire (removes and returns it)
-    pub fn pop_timer(&self) -> Option<Timer> {
-        let mut local_timers = self.timers.lock().expect("Failed to lock mutex");
-        local_timers.pop().map(|item| item.0)
+    fn timer_count(&self) -> usize {
+        self.live.len()
     }
 
-    /// Checks if the timer queue is empty
-    pub fn is_empty(&self) -> bool {
-        let local_timers = self.timers.lock().expect("Failed to lock mutex");
-        local_timers.is_empty()
+    fn pop_timer(&mut self) -> Option<Timer> {
+        self.discard_cancelled();
+        let item = self.heap.pop()?;
+        self.live.remove(&item.timer.id);
+        Some(item.timer)
+    }
+
+    fn clear(&mut self) {
+        self.heap.clear();
+        self.live.clear();
     }
 }
 
-impl Default for Timers {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancelled_timers_are_skipped_and_swept() {
+        let mut timers = Timers::new();
+        let queued: Vec<Timer> = (0..1_000).map(|i| Timer::new_at(10_000 + i, 0)).collect();
+        for timer in &queued {
+            timers.add_timer(timer.clone());
+        }
+        for timer in queued.iter().filter(|timer| timer.expires_at % 10 != 0) {
+            timers.remove_timer(timer.id);
+        }
+        // Replacing a timer leaves only the new expiration queued
+        timers.add_timer(Timer::with_id_at(5, queued[500].id, 0));
+
+        assert_eq!(timers.timer_count(), 100);
+        assert!(timers.heap.len() <= 2 * 100 + 1);
+        assert_eq!(timers.peek_timer().unwrap().expires_at, 5);
+
+        let expires: Vec<u64> = std::iter::from_fn(|| timers.pop_timer()).map(|timer| timer.expires_at).collect();
+        let mut expected: Vec<u64> = (0..100).map(|i| 10_000 + i * 10).filter(|&e| e != 10_500).collect();
+        expected.insert(0, 5);
+        assert_eq!(expires, expected);
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn test_equal_deadlines_pop_by_priority_then_seq() {
+        let now = crate::utils::clock::current_time_ms();
+        let expires_at = now + 10_000;
+        for kind in [QueueKind::BinaryHeap, QueueKind::TimingWheel] {
+            let mut queue = kind.build(now);
+            let timers: Vec<Timer> = (0..10u64)
+                .map(|seq| Timer::new_at(expires_at, 0).with_priority((seq % 3 == 0) as u8).with_seq(seq + 1))
+                .collect();
+            for timer in timers.iter().rev() {
+                queue.add_timer(timer.clone());
+            }
+
+            let order: Vec<u64> = std::iter::from_fn(|| queue.pop_timer()).map(|timer| timer.seq).collect();
+            assert_eq!(order, [1, 4, 7, 10, 2, 3, 5, 6, 8, 9], "{:?}", kind);
+        }
     }
 }
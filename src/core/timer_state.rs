@@ -0,0 +1,143 @@
+use super::{QueueKind, Timer, TimerQueue};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Pending timers and their data.
+///
+/// Every method updates or reads the queue and the data together, so a queued
+/// timer always has its data and the other way round. A `TimerShip`'s state
+/// is owned by its scheduler thread and changed only there.
+#[derive(Debug)]
+pub struct TimerState {
+    queue: Box<dyn TimerQueue>,
+    timers: HashMap<Uuid, (Timer, String)>,
+}
+
+impl TimerState {
+    /// Creates an empty state backed by the given kind of queue, as of `now_ms`
+    /// on the caller's clock
+    pub fn new(kind: QueueKind, now_ms: u64) -> Self {
+        TimerState {
+            queue: kind.build(now_ms),
+            timers: HashMap::new(),
+        }
+    }
+
+    /// Adds a timer with its data, replacing any timer with the same ID
+    pub fn insert(&mut self, timer: Timer, data: String) {
+        self.queue.add_timer(timer.clone());
+        self.timers.insert(timer.id, (timer, data));
+    }
+
+    /// Removes a timer and returns its data
+    pub fn remove(&mut self, timer_id: Uuid) -> Option<String> {
+        let (_, data) = self.timers.remove(&timer_id)?;
+        self.queue.remove_timer(timer_id);
+        Some(data)
+    }
+
+    /// Moves a timer to a new expiration time, returning whether it was queued.
+    ///
+    /// `seq` is the sequence number of the reschedule record, so the timer
+    /// fires after timers already scheduled for the same time.
+    pub fn reschedule(&mut self, timer_id: Uuid, expires_at: u64, seq: u64) -> bool {
+        let Some((timer, _)) = self.timers.get_mut(&timer_id) else {
+            return false;
+        };
+        timer.expires_at = expires_at;
+        timer.seq = seq;
+        let timer = timer.clone();
+        self.queue.add_timer(timer);
+        true
+    }
+
+    /// Moves every timer's expiration time by `offset_ms`, keeping each
+    /// timer's sequence number so timers due together keep their order
+    pub fn shift_all(&mut self, offset_ms: i64) -> usize {
+        for (timer, _) in self.timers.values_mut() {
+            timer.expires_at = timer.expires_at.saturating_add_signed(offset_ms);
+            self.queue.add_timer(timer.clone());
+        }
+        self.timers.len()
+    }
+
+    /// Iterates over the timers that count only uptime
+    pub fn uptime_timers(&self) -> impl Iterator<Item = &Timer> + '_ {
+        self.timers.values().map(|(timer, _)| timer).filter(|timer| timer.uptime)
+    }
+
+    /// Removes and returns the next timer and its data if it has expired by `now`
+    pub fn pop_expired(&mut self, now: u64) -> Option<(Timer, String)> {
+        if !self.queue.peek_timer()?.is_expired(now) {
+            return None;
+        }
+        let timer = self.queue.pop_timer()?;
+        self.timers.remove(&timer.id)
+    }
+
+    /// Gets the timer that expires first
+    pub fn next_timer(&mut self) -> Option<Timer> {
+        self.queue.peek_timer()
+    }
+
+    /// Gets the data of a queued timer
+    pub fn get_data(&self, timer_id: Uuid) -> Option<&str> {
+        self.timers.get(&timer_id).map(|(_, data)| data.as_str())
+    }
+
+    /// Checks whether a timer with this ID is queued
+    pub fn contains(&self, timer_id: Uuid) -> bool {
+        self.timers.contains_key(&timer_id)
+    }
+
+    /// Gets every queued timer with its data, in no particular order
+    pub fn snapshot(&self) -> Vec<(Timer, String)> {
+        self.timers.values().cloned().collect()
+    }
+
+    /// Gets the count of queued timers
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Checks if no timers are queued
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Removes every timer
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.timers.clear();
+    }
+}
+
+impl Default for TimerState {
+    fn default() -> Self {
+        Self::new(QueueKind::default(), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_expired_returns_timer_with_data() {
+        let mut state = TimerState::new(QueueKind::TimingWheel, 0);
+        let due = Timer::new_at(1_000, 0);
+        let later = Timer::new_at(5_000, 0);
+        state.insert(due.clone(), "due".to_string());
+        state.insert(later.clone(), "later".to_string());
+
+        assert!(state.pop_expired(999).is_none());
+        assert!(state.reschedule(later.id, 500, 3));
+        let (timer, data) = state.pop_expired(999).unwrap();
+        assert_eq!((timer.id, timer.expires_at, data.as_str()), (later.id, 500, "later"));
+        assert!(!state.reschedule(later.id, 600, 4));
+
+        assert_eq!(state.remove(due.id).as_deref(), Some("due"));
+        assert_eq!(state.remove(due.id), None);
+        assert!(state.is_empty() && state.snapshot().is_empty());
+    }
+}
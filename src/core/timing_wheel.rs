@@ -0,0 +1,285 @@
+use super::{FireOrder, Timer, TimerQueue};
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// Slots per level, as a power of two
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+
+/// Enough levels of 1ms ticks to cover every `u64` expiration time
+const LEVELS: usize = (u64::BITS as usize).div_ceil(SLOT_BITS as usize);
+
+/// Hierarchical timing wheel with O(1) insert and cancel.
+///
+/// Level `n` has 64 slots each spanning `64^n` milliseconds. A timer sits at
+/// the lowest level whose slot span separates it from the wheel's cursor, and
+/// moves down a level each time the cursor reaches its slot, so finding the
+/// next timer costs at most one cascade per level over a timer's lifetime.
+/// Once the cursor reaches a millisecond, the timers due in it are sorted by
+/// priority and scheduling order. Peeking leaves the wheel untouched and scans
+/// the next occupied slot; only popping advances the cursor.
+#[derive(Debug)]
+pub struct TimingWheel {
+    /// No timer in the wheel expires before this time
+    elapsed: u64,
+    levels: Vec<Level>,
+    /// Timers due at or before `elapsed`, in firing order
+    early: BTreeSet<FireOrder>,
+    entries: HashMap<Uuid, Entry>,
+}
+
+#[derive(Debug)]
+struct Level {
+    /// Bit `n` is set when slot `n` holds timers
+    occupied: u64,
+    slots: Vec<Vec<Uuid>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    timer: Timer,
+    position: Position,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Position {
+    Early,
+    Slot { level: usize, slot: usize, index: usize },
+}
+
+impl TimingWheel {
+    /// Creates a wheel whose cursor starts at `now_ms` (milliseconds since UNIX
+    /// epoch), read from the caller's clock
+    pub fn new(now_ms: u64) -> Self {
+        TimingWheel {
+            elapsed: now_ms,
+            levels: (0..LEVELS)
+                .map(|_| Level {
+                    occupied: 0,
+                    slots: vec![Vec::new(); SLOTS],
+                })
+                .collect(),
+            early: BTreeSet::new(),
+            entries: HashMap::new(),
+        }
+    }
+}
+
+/// Starts the cursor at the UNIX epoch; timers cascade down on the first pop
+impl Default for TimingWheel {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl TimerQueue for TimingWheel {
+    fn add_timer(&mut self, timer: Timer) {
+        self.remove(timer.id);
+        self.insert(timer);
+    }
+
+    fn peek_timer(&mut self) -> Option<Timer> {
+        self.peek().cloned()
+    }
+
+    fn remove_timer(&mut self, timer_id: Uuid) {
+        self.remove(timer_id);
+    }
+
+    fn get_all_timers(&self) -> Vec<Timer> {
+        self.entries.values().map(|entry| entry.timer.clone()).collect()
+    }
+
+    fn timer_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn pop_timer(&mut self) -> Option<Timer> {
+        let timer_id = self.next_timer()?;
+        self.remove(timer_id)
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new(self.elapsed);
+    }
+}
+
+impl TimingWheel {
+    fn insert(&mut self, timer: Timer) {
+        let position = if timer.expires_at <= self.elapsed {
+            self.early.insert(timer.fire_order());
+            Position::Early
+        } else {
+            let level = level_for(self.elapsed, timer.expires_at);
+            let slot = slot_for(timer.expires_at, level);
+            let slots = &mut self.levels[level];
+            slots.occupied |= 1 << slot;
+            slots.slots[slot].push(timer.id);
+            Position::Slot {
+                level,
+                slot,
+                index: slots.slots[slot].len() - 1,
+            }
+        };
+        self.entries.insert(timer.id, Entry { timer, position });
+    }
+
+    fn remove(&mut self, timer_id: Uuid) -> Option<Timer> {
+        let entry = self.entries.remove(&timer_id)?;
+        match entry.position {
+            Position::Early => {
+                self.early.remove(&entry.timer.fire_order());
+            }
+            Position::Slot { level, slot, index } => {
+                let level = &mut self.levels[level];
+                let ids = &mut level.slots[slot];
+                ids.swap_remove(index);
+                if let Some(moved) = ids.get(index) {
+                    if let Some(Entry {
+                        position: Position::Slot { index: moved_index, .. },
+                        ..
+                    }) = self.entries.get_mut(moved)
+                    {
+                        *moved_index = index;
+                    }
+                }
+                if ids.is_empty() {
+                    level.occupied &= !(1 << slot);
+                }
+            }
+        }
+        Some(entry.timer)
+    }
+
+    /// Finds the timer that expires first without moving the cursor.
+    ///
+    /// Timers at a level all expire after those at lower levels, and every
+    /// occupied slot lies at or ahead of the cursor, so the first timer is
+    /// early or in the lowest occupied slot of the lowest occupied level.
+    fn peek(&self) -> Option<&Timer> {
+        if let Some(&(_, _, _, timer_id)) = self.early.first() {
+            return Some(&self.entries[&timer_id].timer);
+        }
+
+        let level = self.levels.iter().position(|level| level.occupied != 0)?;
+        let slot = self.levels[level].occupied.trailing_zeros() as usize;
+        self.levels[level].slots[slot]
+            .iter()
+            .map(|timer_id| &self.entries[timer_id].timer)
+            .min_by_key(|timer| timer.fire_order())
+    }
+
+    /// Finds the timer that expires first, cascading timers down as the cursor advances
+    fn next_timer(&mut self) -> Option<Uuid> {
+        loop {
+            // Early timers expire before anything in the wheel
+            if let Some(&(_, _, _, timer_id)) = self.early.first() {
+                return Some(timer_id);
+            }
+
+            let level = self.levels.iter().position(|level| level.occupied != 0)?;
+            // Every occupied slot lies at or ahead of the cursor, so the lowest is next
+            let slot = self.levels[level].occupied.trailing_zeros() as usize;
+
+            // Move the cursor to the start of that slot and spread its timers over lower
+            // levels; a level 0 slot is a single millisecond, so its timers become early
+            let span_bits = SLOT_BITS * level as u32;
+            let level_mask = 1u64
+                .checked_shl(span_bits + SLOT_BITS)
+                .map_or(u64::MAX, |level_span| level_span - 1);
+            self.elapsed = (self.elapsed & !level_mask) | ((slot as u64) << span_bits);
+
+            let ids = std::mem::take(&mut self.levels[level].slots[slot]);
+            self.levels[level].occupied &= !(1 << slot);
+            for timer_id in ids {
+                if let Some(entry) = self.entries.remove(&timer_id) {
+                    self.insert(entry.timer);
+                }
+            }
+        }
+    }
+}
+
+/// Level at which a timer expiring at `when` sits relative to the cursor
+fn level_for(elapsed: u64, when: u64) -> usize {
+    let differing = (elapsed ^ when) | (SLOTS as u64 - 1);
+    let significant = u64::BITS - 1 - differing.leading_zeros();
+    (significant / SLOT_BITS) as usize
+}
+
+/// Slot within `level` for a timer expiring at `when`
+fn slot_for(when: u64, level: usize) -> usize {
+    ((when >> (SLOT_BITS * level as u32)) & (SLOTS as u64 - 1)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::current_time_ms;
+
+    fn drain(wheel: &mut TimingWheel) -> Vec<u64> {
+        std::iter::from_fn(|| wheel.pop_timer()).map(|timer| timer.expires_at).collect()
+    }
+
+    #[test]
+    fn test_pops_in_expiration_order_across_levels() {
+        let now = current_time_ms();
+        let mut wheel = TimingWheel::new(now);
+        let mut offsets = vec![0, 1, 63, 64, 65, 4_095, 4_096, 300_000, 86_400_000, 1 << 40];
+        for offset in &offsets {
+            wheel.add_timer(Timer::new_at(now + offset, 0));
+        }
+        wheel.add_timer(Timer::new_at(u64::MAX, 0));
+
+        // Advance the cursor, then add timers behind it
+        assert_eq!(wheel.pop_timer().unwrap().expires_at, now);
+        wheel.add_timer(Timer::new_at(now - 5_000, 0));
+        wheel.add_timer(Timer::new_at(now + 2, 0));
+
+        offsets.remove(0);
+        let mut expected: Vec<u64> = offsets.iter().map(|offset| now + offset).collect();
+        expected.extend([now - 5_000, now + 2, u64::MAX]);
+        expected.sort();
+        assert_eq!(drain(&mut wheel), expected);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_leaves_other_timers_in_slot() {
+        let now = current_time_ms();
+        let mut wheel = TimingWheel::new(now);
+        let expires_at = now + 10_000;
+        let timers: Vec<Timer> = (0..5).map(|_| Timer::new_at(expires_at, 0)).collect();
+        for timer in &timers {
+            wheel.add_timer(timer.clone());
+        }
+
+        wheel.remove_timer(timers[0].id);
+        wheel.remove_timer(timers[3].id);
+        wheel.add_timer(Timer::with_id_at(expires_at - 1, timers[1].id, 0));
+        assert_eq!(wheel.timer_count(), 3);
+        assert_eq!(wheel.pop_timer().unwrap().id, timers[1].id);
+
+        let mut rest: Vec<Uuid> = std::iter::from_fn(|| wheel.pop_timer()).map(|timer| timer.id).collect();
+        rest.sort();
+        let mut expected = vec![timers[2].id, timers[4].id];
+        expected.sort();
+        assert_eq!(rest, expected);
+    }
+
+    #[test]
+    fn test_peek_leaves_cursor_in_place() {
+        let mut wheel = TimingWheel::new(1_000);
+        let later = Timer::new_at(1_000 + 86_400_000, 0);
+        let sooner = Timer::new_at(1_000 + 86_400_000 - 1, 0);
+        wheel.add_timer(later);
+        wheel.add_timer(sooner.clone());
+
+        assert_eq!(wheel.peek_timer().unwrap().id, sooner.id);
+        assert_eq!(wheel.elapsed, 1_000);
+        // A timer added after the peek still sits in the wheel, not among early timers
+        wheel.add_timer(Timer::new_at(2_000, 0));
+        assert!(wheel.early.is_empty());
+        assert_eq!(drain(&mut wheel), [2_000, 1_000 + 86_400_000 - 1, 1_000 + 86_400_000]);
+    }
+}
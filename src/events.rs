@@ -0,0 +1,33 @@
+//! Notifications about a `TimerShip` itself, as opposed to its timers firing
+
+use crate::utils::ClockJumpPolicy;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Something a `TimerShip` noticed and reacted to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimerEvent {
+    /// A timer fired; delivered just before the expiration callback runs for it,
+    /// so an event callback can handle expirations without one. `missed` is set
+    /// if it came due while the process was down.
+    Expired { timer_id: Uuid, data: String, missed: bool },
+    /// A missed timer was removed without firing, per the `MissedTimerPolicy`
+    DeadLettered {
+        timer_id: Uuid,
+        data: String,
+        /// How long the timer was overdue by when it was dropped
+        overdue_ms: u64,
+    },
+    /// The wall clock moved `jump_ms` further than monotonic time did, forwards
+    /// if positive and backwards if negative. A sharded instance reports a jump
+    /// once per shard.
+    ClockJump {
+        jump_ms: i64,
+        policy: ClockJumpPolicy,
+        /// Pending timers whose deadlines moved with the clock
+        timers_shifted: usize,
+    },
+}
+
+/// Callback for `TimerEvent`s, run on the same thread as expiration callbacks
+pub type EventCallback = Arc<dyn Fn(&TimerEvent) + Send + Sync>;
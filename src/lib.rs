@@ -2,19 +2,35 @@
 //! 
 //! A persistent timer system with operation logging for failure recovery.
 
+pub mod change_stream;
 pub mod core;
+pub mod events;
 pub mod persistence;
+pub mod replication;
+mod scheduler;
 pub mod utils;
 pub mod timer_ship;
+pub mod timer_view;
+pub mod transfer;
 
 #[cfg(feature = "performance-tests")]
 pub mod testing;
 
 // Re-export main types
-pub use timer_ship::{TimerShip, TimerCallback, TimerInfo};
+pub use timer_ship::{TimerShip, TimerShipConfig, TimerCallback, TimerInfo, TimerOptions};
+pub use change_stream::ChangeStream;
+pub use events::{EventCallback, TimerEvent};
+pub use replication::ReplicationServer;
+pub use scheduler::Completion;
+pub use timer_view::TimerView;
+pub use transfer::{ExportFormat, ExportedTimer};
 pub use core::Timer;
-pub use persistence::{LogEntry, LogOperation};
-pub use utils::{parse_duration, ParseError};
+pub use persistence::{
+    migrate_log, restore_backup, BackupReport, CompactionReport, DirectoryStore, EncryptedStore, KeyRing,
+    LogEntry, LogOperation, MemoryStore, MigrationReport, MissedTimerPolicy, OpLog, RecoveryPolicy, RecoveryReport,
+    RecoveryTarget, ShardedStore, TimerStore, LOG_FORMAT_VERSION,
+};
+pub use utils::{parse_duration, parse_timestamp, Clock, ClockJumpPolicy, MockClock, ParseError, SystemClock};
 pub use uuid::Uuid;
 
 /// Result type for timer operations
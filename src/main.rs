@@ -1,9 +1,27 @@
-use timer_ship::{TimerShip, TimerCallback, TimerInfo};
-use std::{io::{self, Write}, thread, time::Duration};
-use log::{info, error};
-use env_logger;
+use timer_ship::{
+    migrate_log, parse_timestamp, restore_backup,
+    persistence::{log_stats, verify_log, LogRecord},
+    transfer::{read_timers, write_timers},
+    EncryptedStore, ExportFormat, ExportedTimer, KeyRing, LogOperation, OpLog, RecoveryTarget, TimerCallback,
+    TimerInfo, TimerShip, TimerShipConfig, TimerStore, TimerView, LOG_FORMAT_VERSION,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use log::{info, error, warn};
 use uuid::Uuid;
 
+const DEFAULT_LOG_PATH: &str = "timer_operations.log";
+
+/// Environment variable naming the key file of an encrypted log
+const KEY_FILE_VAR: &str = "TIMER_SHIP_KEY_FILE";
+
 fn print_menu() {
     println!("\n🚢 Timer Ship - Interactive CLI");
     println!("═══════════════════════════════");
@@ -11,7 +29,8 @@ fn print_menu() {
     println!("2. List duration format examples");
     println!("3. List active timers");
     println!("4. Remove specific timer");
-    println!("5. Exit");
+    println!("5. Back up timers");
+    println!("6. Exit");
     println!("\nDuration formats:");
     println!("  • Milliseconds: 100ms, 1500ms (integers only)");
     println!("  • Seconds: 1s, 2.5s, 30s");
@@ -48,18 +67,10 @@ fn show_duration_examples() {
     println!("  • '0.5h' - 30 minutes");
 }
 
-fn list_active_timers(timer_ship: &TimerShip) {
-    let timers = timer_ship.list_active_timers();
-    
-    if timers.is_empty() {
-        println!("\n📭 No active timers found.");
-        return;
-    }
-    
-    println!("\n📋 Active Timers ({} total):", timers.len());
-    println!("┌────────────────────────────────────────┬─────────────────┬──────────────────────────────────────────┐");
-    println!("│ Timer ID                               │ Time Left       │ Description                              │");
-    println!("├────────────────────────────────────────┼─────────────────┼──────────────────────────────────────────┤");
+fn print_timer_table(timers: &[TimerInfo]) {
+    println!("┌────────────────────────────────────────┬─────────────────┬──────────┬──────────────────────────────────────────┐");
+    println!("│ Timer ID                               │ Time Left       │ Progress │ Description                              │");
+    println!("├────────────────────────────────────────┼─────────────────┼──────────┼──────────────────────────────────────────┤");
     
     for (index, timer) in timers.iter().enumerate() {
         let id_short = format!("{}...{}", 
@@ -76,15 +87,35 @@ fn list_active_timers(timer_ship: &TimerShip) {
         
         let status_icon = if timer.time_left_ms == 0 { "🔴" } else { "⏰" };
         
-        println!("│ {}{:<38} │ {:<15} │ {:<40} │", 
-            status_icon, id_short, time_left, description);
+        let progress = format!("{:.0}%", timer.percent_complete());
+
+        println!("│ {}{:<38} │ {:<15} │ {:>8} │ {:<40} │", 
+            status_icon, id_short, time_left, progress, description);
         
         if index < timers.len() - 1 {
-            println!("├────────────────────────────────────────┼─────────────────┼──────────────────────────────────────────┤");
+            println!("├────────────────────────────────────────┼─────────────────┼──────────┼──────────────────────────────────────────┤");
         }
     }
     
-    println!("└────────────────────────────────────────┴─────────────────┴──────────────────────────────────────────┘");
+    println!("└────────────────────────────────────────┴─────────────────┴──────────┴──────────────────────────────────────────┘");
+}
+
+fn list_active_timers(timer_ship: &TimerShip) {
+    let timers = match timer_ship.try_list_active_timers() {
+        Ok(timers) => timers,
+        Err(e) => {
+            println!("❌ Failed to list timers: {}", e);
+            return;
+        }
+    };
+    
+    if timers.is_empty() {
+        println!("\n📭 No active timers found.");
+        return;
+    }
+    
+    println!("\n📋 Active Timers ({} total):", timers.len());
+    print_timer_table(&timers);
     
     // Show summary
     let expired_count = timers.iter().filter(|t| t.time_left_ms == 0).count();
@@ -97,8 +128,27 @@ fn list_active_timers(timer_ship: &TimerShip) {
     }
 }
 
+fn backup_timers(timer_ship: &TimerShip) {
+    let dest = get_user_input("Enter backup destination (must not exist): ");
+    if dest.is_empty() {
+        println!("❌ Destination cannot be empty!");
+        return;
+    }
+
+    match timer_ship.backup_to(&dest) {
+        Ok(report) => println!("💾 Backed up to {} ({} files, {} bytes)", dest, report.files, report.bytes),
+        Err(e) => println!("❌ Failed to back up timers: {}", e),
+    }
+}
+
 fn remove_timer_by_id(timer_ship: &TimerShip) {
-    let timers = timer_ship.list_active_timers();
+    let timers = match timer_ship.try_list_active_timers() {
+        Ok(timers) => timers,
+        Err(e) => {
+            println!("❌ Failed to list timers: {}", e);
+            return;
+        }
+    };
     
     if timers.is_empty() {
         println!("\n📭 No active timers to remove.");
@@ -148,7 +198,7 @@ fn interactive_mode(timer_ship: &TimerShip) {
     loop {
         print_menu();
         
-        let choice = get_user_input("\nEnter your choice (1-5): ");
+        let choice = get_user_input("\nEnter your choice (1-6): ");
         
         match choice.as_str() {
             "1" => {
@@ -189,11 +239,14 @@ fn interactive_mode(timer_ship: &TimerShip) {
                 remove_timer_by_id(timer_ship);
             },
             "5" => {
+                backup_timers(timer_ship);
+            },
+            "6" => {
                 println!("👋 Goodbye! Timers will continue running in background...");
                 break;
             },
             _ => {
-                println!("❌ Invalid choice. Please enter 1-5.");
+                println!("❌ Invalid choice. Please enter 1-6.");
             }
         }
         
@@ -224,6 +277,282 @@ fn demo_mode(timer_ship: &TimerShip) {
     info!("🎯 Demo timers set! Watch for expiration messages...");
 }
 
+fn migrate_command(log_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔧 Migrating {} to log format version {}...", log_path, LOG_FORMAT_VERSION);
+
+    let report = migrate_log(log_path)?;
+
+    println!("✅ Migration complete ({} records)", report.total());
+    println!("   Upgraded:   {}", report.upgraded);
+    println!("   Current:    {}", report.current);
+    if report.unreadable > 0 {
+        println!("⚠️  Unreadable: {} (kept as-is)", report.unreadable);
+    }
+    Ok(())
+}
+
+/// Reads the key ring from the file named by `TIMER_SHIP_KEY_FILE`, if it is set.
+///
+/// Each line holds `<key id>:<base64 key>`. The first key encrypts new records
+/// and the rest keep records written with older keys readable.
+fn load_keys() -> Result<Option<KeyRing>, Box<dyn std::error::Error>> {
+    let Some(path) = std::env::var_os(KEY_FILE_VAR) else {
+        return Ok(None);
+    };
+    let path = path.to_string_lossy().into_owned();
+    let mut ring: Option<KeyRing> = None;
+    for line in std::fs::read_to_string(&path)?.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key_id, encoded) = line
+            .split_once(':')
+            .ok_or_else(|| format!("{}: expected <key id>:<base64 key>", path))?;
+        let key_id: u32 = key_id.parse()?;
+        let key: [u8; 32] = BASE64
+            .decode(encoded)?
+            .try_into()
+            .map_err(|_| format!("{}: key {} is not 32 bytes", path, key_id))?;
+        ring = Some(match ring {
+            Some(ring) => ring.with_key(key_id, key),
+            None => KeyRing::new(key_id, key),
+        });
+    }
+    ring.map(Some).ok_or_else(|| format!("{} holds no keys", path).into())
+}
+
+/// Opens a log read-only, decrypting its payloads if a key file is given
+fn open_log_store(log_path: &str) -> Result<Arc<dyn TimerStore>, Box<dyn std::error::Error>> {
+    let log = OpLog::open_read_only(log_path)?;
+    Ok(match load_keys()? {
+        Some(keys) => Arc::new(EncryptedStore::new(log, keys)),
+        None => Arc::new(log),
+    })
+}
+
+fn format_timestamp(timestamp_ms: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp_ms as i64)
+        .map(|utc| utc.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S%.3f %Z").to_string())
+        .unwrap_or_else(|| timestamp_ms.to_string())
+}
+
+fn at_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "Usage: timer_ship at <time> [log_path] | timer_ship at --entry <index> [log_path]";
+    let (target, log_path) = match args.first().map(|s| s.as_str()) {
+        Some("--entry") => {
+            let index = args.get(1).ok_or(usage)?.parse::<usize>()?;
+            (RecoveryTarget::EntryIndex(index), args.get(2))
+        }
+        Some(time) => (RecoveryTarget::Timestamp(parse_timestamp(time)?), args.get(1)),
+        None => return Err(usage.into()),
+    };
+    let log_path = log_path.map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+
+    let view = TimerView::from_store(open_log_store(log_path)?, target)?;
+    let timers = view.timers();
+
+    println!(
+        "\n🕒 Timers live at {} ({} entries replayed)",
+        format_timestamp(view.as_of()),
+        view.report().entries_replayed
+    );
+    if timers.is_empty() {
+        println!("📭 No timers were live at that moment.");
+        return Ok(());
+    }
+
+    print_timer_table(timers);
+    println!("\n📊 Summary: {} live, {} already due", timers.len(), view.report().overdue_timers);
+    Ok(())
+}
+
+fn log_dump(log: OpLog) -> Result<(), Box<dyn std::error::Error>> {
+    let records = log.read_records()?;
+    let records = match load_keys()? {
+        Some(keys) => EncryptedStore::new(log, keys).open_records(records),
+        None => records,
+    };
+    for (index, record) in records.into_iter().enumerate() {
+        match record {
+            LogRecord::Entry { entry, .. } => {
+                let time = format_timestamp(entry.timestamp);
+                match entry.operation {
+                    LogOperation::SetTimer { timer_id, expires_at, data, duration_ms, .. } => {
+                        println!(
+                            "#{:<6} {}  SET     {}  expires {} ({}ms)  {:?}",
+                            index, time, timer_id, format_timestamp(expires_at), duration_ms, data
+                        );
+                    }
+                    LogOperation::RemoveTimer { timer_id } => {
+                        println!("#{:<6} {}  REMOVE  {}", index, time, timer_id);
+                    }
+                    LogOperation::FireTimer { timer_id } => {
+                        println!("#{:<6} {}  FIRE    {}", index, time, timer_id);
+                    }
+                    LogOperation::RescheduleTimer { timer_id, expires_at } => {
+                        println!(
+                            "#{:<6} {}  RESCHED {}  expires {}",
+                            index, time, timer_id, format_timestamp(expires_at)
+                        );
+                    }
+                    LogOperation::Checkpoint => {
+                        println!("#{:<6} {}  CHECKPT", index, time);
+                    }
+                    LogOperation::ShiftDeadlines { offset_ms } => {
+                        println!("#{:<6} {}  SHIFT   all deadlines by {}ms", index, time, offset_ms);
+                    }
+                    LogOperation::Compacted => {
+                        println!("#{:<6} {}  COMPACT history up to here discarded", index, time);
+                    }
+                }
+            }
+            LogRecord::Corrupt(corrupt) => {
+                println!("#{:<6} ❌ CORRUPT at {}: {}", index, corrupt.location, corrupt.error);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn log_verify(log: &OpLog) -> Result<(), Box<dyn std::error::Error>> {
+    let report = verify_log(log)?;
+
+    println!("🔍 Verified {} entries in {}", report.entries, log.path());
+    for corrupt in &report.corrupt {
+        println!("   ❌ {}: undecodable record: {}", corrupt.location, corrupt.error);
+    }
+    for issue in &report.issues {
+        println!("   ⚠️  {}", issue);
+    }
+
+    if report.is_ok() {
+        println!("✅ Log is decodable and consistent");
+        Ok(())
+    } else {
+        Err(format!(
+            "{} corrupt records, {} consistency issues",
+            report.corrupt.len(),
+            report.issues.len()
+        )
+        .into())
+    }
+}
+
+fn log_stats_command(log: &OpLog) -> Result<(), Box<dyn std::error::Error>> {
+    let stats = log_stats(log)?;
+
+    println!("📊 Log statistics for {}", log.path());
+    println!("   Size:            {} bytes in {} file(s)", stats.size_bytes, stats.segments);
+    println!(
+        "   Entries:         {} ({} set, {} remove, {} fire, {} reschedule, {} checkpoint, {} shift, {} corrupt)",
+        stats.total_entries,
+        stats.set_entries,
+        stats.remove_entries,
+        stats.fire_entries,
+        stats.reschedule_entries,
+        stats.checkpoint_entries,
+        stats.shift_entries,
+        stats.corrupt_records
+    );
+    println!("   Live timers:     {}", stats.live_timers);
+    println!("   Removed timers:  {}", stats.removed_timers);
+    if let (Some(first), Some(last)) = (stats.first_timestamp, stats.last_timestamp) {
+        println!("   Time span:       {} → {}", format_timestamp(first), format_timestamp(last));
+    }
+    if let Some((timer_id, set_at)) = stats.oldest_live {
+        println!("   Oldest live:     {} (set {})", timer_id, format_timestamp(set_at));
+    }
+    if let Some(through) = stats.compacted_through {
+        println!("   Compacted up to: {}", format_timestamp(through));
+    }
+    Ok(())
+}
+
+fn log_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "Usage: timer_ship log <dump|verify|stats> [log_path]";
+    let subcommand = args.first().ok_or(usage)?;
+    let log_path = args.get(1).map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+    let log = OpLog::open_read_only(log_path)?;
+
+    match subcommand.as_str() {
+        "dump" => log_dump(log),
+        "verify" => log_verify(&log),
+        "stats" => log_stats_command(&log),
+        _ => Err(usage.into()),
+    }
+}
+
+fn export_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let out_path = args.first().ok_or("Usage: timer_ship export <file.json|file.csv> [log_path]")?;
+    let log_path = args.get(1).map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+
+    let view = TimerView::from_store(open_log_store(log_path)?, RecoveryTarget::Latest)?;
+    let timers: Vec<ExportedTimer> = view.timers().iter().map(ExportedTimer::from).collect();
+    write_timers(BufWriter::new(File::create(out_path)?), &timers, ExportFormat::from_path(out_path))?;
+
+    println!("📤 Exported {} live timers from {} to {}", timers.len(), log_path, out_path);
+    Ok(())
+}
+
+fn import_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let in_path = args.first().ok_or("Usage: timer_ship import <file.json|file.csv> [log_path]")?;
+    let log_path = args.get(1).map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+
+    let format = ExportFormat::from_path(in_path);
+    let input = std::fs::read(in_path)?;
+    let total = read_timers(&input[..], format)?.len();
+
+    // Paused so timers already due are left for the application to fire
+    let config = TimerShipConfig {
+        paused: true,
+        encryption_key: load_keys()?,
+        ..TimerShipConfig::default()
+    };
+    let imported = TimerShip::open(log_path, None, config)?.import(&input[..], format)?;
+
+    println!(
+        "📥 Imported {} timers into {} ({} already present)",
+        imported,
+        log_path,
+        total - imported
+    );
+    Ok(())
+}
+
+fn backup_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let dest = args.first().ok_or("Usage: timer_ship backup <dest> [log_path]")?;
+    let log_path = args.get(1).map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+
+    // A running instance holds the log exclusively; only it can take a consistent copy
+    let log = match OpLog::open_read_only(log_path) {
+        Err(e) if e.kind() == io::ErrorKind::ResourceBusy => {
+            return Err(format!(
+                "{} is open in a running timer_ship; back it up from its menu (option 5) instead",
+                log_path
+            )
+            .into());
+        }
+        log => log?,
+    };
+    let report = log.backup_to(Path::new(dest))?;
+
+    println!("💾 Backed up {} to {} ({} files, {} bytes)", log_path, dest, report.files, report.bytes);
+    Ok(())
+}
+
+fn restore_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "Usage: timer_ship restore <backup> [log_path] [--force]";
+    let replace = args.iter().any(|arg| arg == "--force");
+    let mut paths = args.iter().filter(|arg| *arg != "--force");
+    let backup_path = paths.next().ok_or(usage)?;
+    let log_path = paths.next().map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+
+    let report = restore_backup(backup_path, log_path, replace)?;
+
+    println!("♻️  Restored {} into {} ({} files, {} bytes)", backup_path, log_path, report.files, report.bytes);
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the logger with default level if RUST_LOG is not set
     if std::env::var("RUST_LOG").is_err() {
@@ -231,6 +560,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     env_logger::init();
 
+    // Check command line arguments for mode
+    let args: Vec<String> = std::env::args().collect();
+    let mode = args.get(1).map(|s| s.as_str()).unwrap_or("interactive");
+
+    // Maintenance commands operate on the log without starting the timer thread
+    match mode {
+        "migrate" => {
+            let log_path = args.get(2).map(|s| s.as_str()).unwrap_or(DEFAULT_LOG_PATH);
+            return migrate_command(log_path);
+        }
+        "at" => return at_command(&args[2..]),
+        "log" => return log_command(&args[2..]),
+        "export" => return export_command(&args[2..]),
+        "import" => return import_command(&args[2..]),
+        "backup" => return backup_command(&args[2..]),
+        "restore" => return restore_command(&args[2..]),
+        _ => {}
+    }
+
     println!("🚢 Starting Timer Ship Application");
 
     // Create a callback function for timer expiration
@@ -266,11 +614,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     // Create TimerShip with callback
-    let timer_ship = TimerShip::with_callback("timer_operations.log", Some(callback))?;
-    
-    // Check command line arguments for mode
-    let args: Vec<String> = std::env::args().collect();
-    let mode = args.get(1).map(|s| s.as_str()).unwrap_or("interactive");
+    let config = TimerShipConfig {
+        encryption_key: load_keys()?,
+        ..TimerShipConfig::default()
+    };
+    let timer_ship = TimerShip::open(DEFAULT_LOG_PATH, Some(callback), config)?;
+
+    let report = timer_ship.recovery_report();
+    info!(
+        "📦 Recovered {} timers ({} overdue) from {} log entries in {:?}",
+        report.live_timers, report.overdue_timers, report.entries_replayed, report.duration
+    );
+    if report.corrupt_records > 0 {
+        warn!("⚠️  Skipped {} corrupt log records during recovery", report.corrupt_records);
+    }
     
     match mode {
         "demo" => {
@@ -282,11 +639,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 thread::sleep(Duration::from_secs(1));
             }
         },
-        "interactive" | _ => {
+        _ => {
             info!("🎮 Starting interactive mode");
             interactive_mode(&timer_ship);
         }
     }
-    
+
+    timer_ship.shutdown();
     Ok(())
 }
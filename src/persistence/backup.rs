@@ -0,0 +1,210 @@
+use super::segment::segment_files;
+use super::OpLog;
+use log::info;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Summary of a backup or restore
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupReport {
+    /// Files written to the destination
+    pub files: usize,
+    /// Bytes written to the destination
+    pub bytes: u64,
+}
+
+/// Restores a backup made by `backup_to` into the log at `log_path`.
+///
+/// `backup_path` is a log file or segment directory; the restored log has the
+/// same layout. The target log is locked while it is restored, so this fails
+/// if a running `TimerShip` has it open. Unless `replace` is set, a target log
+/// that already holds records is left alone and `ErrorKind::AlreadyExists` is
+/// returned.
+pub fn restore_backup(backup_path: &str, log_path: &str, replace: bool) -> io::Result<BackupReport> {
+    let backup = OpLog::open_read_only(backup_path)?;
+    let contents = backup.read_contents()?;
+    if !contents.corrupt.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("backup {} has {} undecodable records", backup_path, contents.corrupt.len()),
+        ));
+    }
+
+    let target = Path::new(log_path);
+    let names_dir = target.is_dir() || log_path.ends_with('/') || log_path.ends_with('\\');
+    if (backup.is_segmented() && target.is_file()) || (!backup.is_segmented() && names_dir) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} and {} are not both single files or both segment directories", backup_path, log_path),
+        ));
+    }
+    // Held until the restore is done, so nothing else opens the log halfway through
+    let target = OpLog::lock_exclusive(log_path, backup.is_segmented())?;
+    if !replace && Path::new(log_path).exists() && !target.read_contents()?.entries.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("log {} already holds records", log_path),
+        ));
+    }
+
+    let mut report = BackupReport::default();
+    if backup.is_segmented() {
+        // Copied into a directory next to the target and swapped in whole, so a
+        // restore that fails partway leaves the old log untouched
+        let dir = Path::new(log_path.trim_end_matches(['/', '\\']));
+        let staging = sibling_path(dir, "restore.tmp")?;
+        let replaced = sibling_path(dir, "restore.old")?;
+        for leftover in [&staging, &replaced] {
+            if leftover.exists() {
+                fs::remove_dir_all(leftover)?;
+            }
+        }
+
+        // Locked so the swapped-in log stays closed to others until we return
+        let staged = OpLog::lock_exclusive(&staging.to_string_lossy(), true)?;
+        for (_, path) in segment_files(Path::new(backup_path))? {
+            let name = path.file_name().expect("segment files have names");
+            report.bytes += copy_file(&path, &staging.join(name), None)?;
+            report.files += 1;
+        }
+        restore_sequence(&backup, &staged, &mut report)?;
+
+        fs::rename(dir, &replaced)?;
+        if let Err(e) = fs::rename(&staging, dir) {
+            fs::rename(&replaced, dir)?;
+            return Err(e);
+        }
+        fs::remove_dir_all(&replaced)?;
+    } else {
+        report.bytes = copy_file(Path::new(backup_path), Path::new(log_path), None)?;
+        report.files = 1;
+        restore_sequence(&backup, &target, &mut report)?;
+    }
+
+    info!(
+        "Restored {} into {}: {} files, {} bytes",
+        backup_path, log_path, report.files, report.bytes
+    );
+    Ok(report)
+}
+
+/// Copies the backup's sequence file over the target's, or removes the
+/// target's if the backup has none
+fn restore_sequence(backup: &OpLog, target: &OpLog, report: &mut BackupReport) -> io::Result<()> {
+    let source = backup.sequence_path();
+    if source.exists() {
+        report.bytes += copy_file(&source, &target.sequence_path(), None)?;
+        report.files += 1;
+        return Ok(());
+    }
+    match fs::remove_file(target.sequence_path()) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Path next to `path` with `suffix` added to its name
+fn sibling_path(path: &Path, suffix: &str) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} does not name a directory", path.display()))
+    })?;
+    Ok(path.with_file_name(format!("{}.{}", name.to_string_lossy(), suffix)))
+}
+
+/// Copies `src` to `dst` via a temporary file and rename, stopping after `limit` bytes if given.
+///
+/// Returns the number of bytes copied.
+pub(crate) fn copy_file(src: &Path, dst: &Path, limit: Option<u64>) -> io::Result<u64> {
+    let tmp_path = dst.with_extension("backup.tmp");
+    let mut reader: Box<dyn Read> = match limit {
+        Some(limit) => Box::new(File::open(src)?.take(limit)),
+        None => Box::new(File::open(src)?),
+    };
+
+    let mut file = File::create(&tmp_path)?;
+    let copied = io::copy(&mut reader, &mut file)?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, dst)?;
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{LogEntry, LogOperation};
+    use uuid::Uuid;
+
+    fn append_timers(log: &OpLog, count: usize) {
+        for _ in 0..count {
+            let operation = LogOperation::SetTimer {
+                timer_id: Uuid::new_v4(),
+                expires_at: u64::MAX,
+                data: "x".repeat(100),
+                created_at: 0,
+                duration_ms: u64::MAX,
+                priority: 0,
+                uptime: false,
+                key: None,
+            };
+            log.append_log(LogEntry::new(1, operation)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_backup_and_restore_segmented_log() {
+        let root = std::env::temp_dir().join(format!("timer_ship_backup_{}", Uuid::new_v4()));
+        let source = root.join("source");
+        let backup = root.join("backup");
+        let restored = root.join("restored");
+
+        let log = OpLog::segmented(source.to_str().unwrap(), 512).unwrap();
+        append_timers(&log, 20);
+        let report = log.backup_to(&backup).unwrap();
+        append_timers(&log, 5);
+        assert!(report.files > 1);
+        assert!(log.backup_to(&backup).is_err());
+
+        let restored = restored.to_str().unwrap();
+        let restore_report = restore_backup(backup.to_str().unwrap(), restored, false).unwrap();
+        assert_eq!(restore_report.files, report.files);
+        assert_eq!(segment_files(Path::new(restored)).unwrap().len(), report.files);
+        assert_eq!(OpLog::open_read_only(restored).unwrap().read_logs().unwrap().len(), 20);
+
+        let err = restore_backup(backup.to_str().unwrap(), restored, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        restore_backup(backup.to_str().unwrap(), restored, true).unwrap();
+        assert_eq!(OpLog::open_read_only(restored).unwrap().read_logs().unwrap().len(), 20);
+        assert!(!root.join("restored.restore.tmp").exists());
+        assert!(!root.join("restored.restore.old").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_restore_keeps_sequence_after_compaction() {
+        let root = std::env::temp_dir().join(format!("timer_ship_backup_{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let source = root.join("timers.log");
+        let backup = root.join("timers.bak");
+        let restored = root.join("restored.log");
+
+        let log = OpLog::new(source.to_str().unwrap()).unwrap();
+        append_timers(&log, 3);
+        for entry in log.read_logs().unwrap() {
+            let timer_id = entry.operation.timer_id().unwrap();
+            log.append_log(LogEntry::new(2, LogOperation::RemoveTimer { timer_id })).unwrap();
+        }
+        log.compact().unwrap();
+        log.backup_to(&backup).unwrap();
+        drop(log);
+
+        restore_backup(backup.to_str().unwrap(), restored.to_str().unwrap(), false).unwrap();
+        let restored = OpLog::new(restored.to_str().unwrap()).unwrap();
+        let seq = restored.append_log(LogEntry::new(3, LogOperation::Checkpoint)).unwrap();
+        assert!(seq > 6, "sequence numbers restarted at {}", seq);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
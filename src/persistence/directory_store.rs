@@ -0,0 +1,287 @@
+use super::{
+    format::{decode_entry, encode_entry},
+    backup::copy_file,
+    oplog::{apply_reschedule, apply_shift},
+    BackupReport, CompactionReport, CorruptRecord, LogContents, LogEntry, LogOperation, TimerStore,
+};
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+use uuid::Uuid;
+
+/// Store that keeps one file per live timer in a directory.
+///
+/// Setting a timer writes `<timer_id>.json` holding its `SetTimer` record and
+/// removing it deletes the file, so the directory always mirrors the live set
+/// and never needs compacting. Removal history is not kept, so
+/// `changes_since` only reports timers that are still live; the last sequence
+/// number handed out is kept in a `SEQUENCE` file and the latest checkpoint
+/// in `CHECKPOINT`.
+#[derive(Debug, Clone)]
+pub struct DirectoryStore {
+    dir: PathBuf,
+    /// Sequence number of the last append, held while an append is written
+    last_seq: Arc<Mutex<u64>>,
+    /// Shared by writers, held exclusively while a backup copies the directory
+    barrier: Arc<RwLock<()>>,
+}
+
+impl DirectoryStore {
+    /// Opens (creating if needed) a directory store at `dir`
+    pub fn new(dir: &str) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut store = DirectoryStore {
+            dir: PathBuf::from(dir),
+            last_seq: Arc::new(Mutex::new(0)),
+            barrier: Arc::new(RwLock::new(())),
+        };
+
+        let recorded = match fs::read_to_string(store.seq_path()) {
+            Ok(recorded) => recorded.trim().parse::<u64>().unwrap_or(0),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let newest = store.read_all()?.entries.iter().map(|entry| entry.seq).max().unwrap_or(0);
+        store.last_seq = Arc::new(Mutex::new(recorded.max(newest)));
+        Ok(store)
+    }
+
+    fn seq_path(&self) -> PathBuf {
+        self.dir.join("SEQUENCE")
+    }
+
+    fn checkpoint_path(&self) -> PathBuf {
+        self.dir.join("CHECKPOINT")
+    }
+
+    fn timer_path(&self, timer_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.json", timer_id))
+    }
+
+    /// Lists the timer files in the directory, named `<timer_id>.json`
+    fn timer_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_timer = path.extension().is_some_and(|ext| ext == "json")
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| Uuid::parse_str(stem).is_ok());
+            if is_timer {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Lists the files holding records: the timer files and any checkpoint
+    fn record_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = self.timer_files()?;
+        let checkpoint = self.checkpoint_path();
+        if checkpoint.exists() {
+            files.push(checkpoint);
+        }
+        Ok(files)
+    }
+}
+
+impl TimerStore for DirectoryStore {
+    fn append(&self, entry: LogEntry) -> std::io::Result<u64> {
+        self.append_with(entry, &|_| {})
+    }
+
+    fn append_with(&self, mut entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        let _barrier = self.barrier.read().expect("Failed to lock backup barrier");
+        let mut last_seq = self.last_seq.lock().expect("Failed to lock sequence number");
+        entry.seq = *last_seq + 1;
+        prepare(&mut entry);
+
+        match &entry.operation {
+            LogOperation::SetTimer { timer_id, .. } => {
+                let path = self.timer_path(*timer_id);
+                write_atomic(&path, encode_entry(&entry)?.as_bytes())?;
+            }
+            LogOperation::RemoveTimer { timer_id } | LogOperation::FireTimer { timer_id } => {
+                match fs::remove_file(self.timer_path(*timer_id)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                // The removed file took its sequence number with it
+                write_atomic(&self.seq_path(), entry.seq.to_string().as_bytes())?;
+            }
+            LogOperation::RescheduleTimer { timer_id, .. } => {
+                let path = self.timer_path(*timer_id);
+                match fs::read_to_string(&path) {
+                    Ok(raw) => {
+                        let mut set = decode_entry(raw.trim())?;
+                        apply_reschedule(&mut set, &entry);
+                        write_atomic(&path, encode_entry(&set)?.as_bytes())?;
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        write_atomic(&self.seq_path(), entry.seq.to_string().as_bytes())?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            LogOperation::Checkpoint => {
+                write_atomic(&self.checkpoint_path(), encode_entry(&entry)?.as_bytes())?;
+            }
+            LogOperation::ShiftDeadlines { offset_ms } => {
+                for path in self.timer_files()? {
+                    let mut set = decode_entry(fs::read_to_string(&path)?.trim())?;
+                    apply_shift(&mut set, *offset_ms);
+                    write_atomic(&path, encode_entry(&set)?.as_bytes())?;
+                }
+                write_atomic(&self.seq_path(), entry.seq.to_string().as_bytes())?;
+            }
+            // The directory holds only live timers, so there is no history to mark
+            LogOperation::Compacted => {}
+        }
+
+        *last_seq = entry.seq;
+        Ok(entry.seq)
+    }
+
+    fn read_all(&self) -> std::io::Result<LogContents> {
+        let mut contents = LogContents::default();
+        for path in self.record_files()? {
+            let raw = fs::read_to_string(&path)?;
+            match decode_entry(raw.trim()) {
+                Ok(entry) => contents.entries.push(entry),
+                Err(e) => contents.corrupt.push(CorruptRecord {
+                    location: path.display().to_string(),
+                    raw,
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        contents.entries.sort_by_key(|entry| (entry.seq, entry.timestamp));
+        Ok(contents)
+    }
+
+    fn compact(&self) -> std::io::Result<CompactionReport> {
+        let count = self.timer_files()?.len();
+        Ok(CompactionReport {
+            entries_before: count,
+            entries_after: count,
+            segments_removed: 0,
+        })
+    }
+
+    fn repair(&self) -> std::io::Result<Vec<CorruptRecord>> {
+        let corrupt = self.read_all()?.corrupt;
+        if corrupt.is_empty() {
+            return Ok(corrupt);
+        }
+
+        let quarantine_dir = self.quarantine_dir();
+        fs::create_dir_all(&quarantine_dir)?;
+        for record in &corrupt {
+            let path = PathBuf::from(&record.location);
+            if let Some(name) = path.file_name() {
+                fs::rename(&path, quarantine_dir.join(name))?;
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Copies the timer files, checkpoint and sequence file into the directory `dest`, blocking writes until done
+    fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport> {
+        if dest.exists() {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("backup destination {} already exists", dest.display()),
+            ));
+        }
+
+        let _barrier = self.barrier.write().expect("Failed to lock backup barrier");
+        fs::create_dir_all(dest)?;
+        let mut report = BackupReport::default();
+        for path in self.record_files()? {
+            let name = path.file_name().expect("timer files have names");
+            report.bytes += copy_file(&path, &dest.join(name), None)?;
+            report.files += 1;
+        }
+        let sequence = self.seq_path();
+        if sequence.exists() {
+            report.bytes += copy_file(&sequence, &dest.join("SEQUENCE"), None)?;
+            report.files += 1;
+        }
+        Ok(report)
+    }
+}
+
+impl DirectoryStore {
+    /// Directory that receives timer files removed by `repair`
+    pub fn quarantine_dir(&self) -> PathBuf {
+        self.dir.join("quarantine")
+    }
+}
+
+/// Writes a file via a temporary sibling and rename, so readers never see a partial record
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_mirrors_live_timers() {
+        let dir = std::env::temp_dir().join(format!("timer_ship_dirstore_{}", Uuid::new_v4()));
+        let store = DirectoryStore::new(dir.to_str().unwrap()).unwrap();
+        let kept = Uuid::new_v4();
+        let removed = Uuid::new_v4();
+
+        for timer_id in [kept, removed] {
+            let operation = LogOperation::SetTimer {
+                timer_id,
+                expires_at: 1000,
+                data: "payload".to_string(),
+                created_at: 0,
+                duration_ms: 1000,
+                priority: 0,
+                uptime: false,
+                key: None,
+            };
+            store.append(LogEntry::new(1, operation)).unwrap();
+        }
+        store
+            .append(LogEntry::new(2, LogOperation::RemoveTimer { timer_id: removed }))
+            .unwrap();
+
+        let entries = store.read_all().unwrap().entries;
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(
+            entries[0].operation,
+            LogOperation::SetTimer { timer_id, .. } if timer_id == kept
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_after_checkpoint_keeps_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("timer_ship_dirstore_{}", Uuid::new_v4()));
+        let store = DirectoryStore::new(dir.to_str().unwrap()).unwrap();
+        store.append(LogEntry::new(1, LogOperation::Checkpoint)).unwrap();
+        fs::write(dir.join(format!("{}.json", Uuid::new_v4())), "{not json").unwrap();
+
+        let quarantined = store.repair().unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(store.compact().unwrap().entries_after, 0);
+        let contents = store.read_all().unwrap();
+        assert!(contents.corrupt.is_empty());
+        assert!(matches!(contents.entries[..], [LogEntry { operation: LogOperation::Checkpoint, .. }]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
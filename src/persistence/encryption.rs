@@ -0,0 +1,389 @@
+use super::{
+    BackupReport, CompactionReport, CorruptRecord, LogContents, LogEntry, LogOperation, LogRecord, TimerStore,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use std::{collections::HashMap, path::Path};
+use uuid::Uuid;
+
+/// Prefix marking an encrypted field, followed by `<seq>:<key id>:<base64>`
+const ENCRYPTED_PREFIX: &str = "enc:v2:";
+
+/// Prefix of fields encrypted before they were bound to their record, followed
+/// by `<key id>:<base64>`
+const LEGACY_PREFIX: &str = "enc:v1:";
+
+/// Length of a ChaCha20-Poly1305 nonce in bytes
+const NONCE_LEN: usize = 12;
+
+/// Set of encryption keys identified by number, one of which is used for new records.
+///
+/// Older keys stay in the ring after rotation so records written with them
+/// remain readable.
+#[derive(Clone)]
+pub struct KeyRing {
+    active: u32,
+    keys: HashMap<u32, ChaCha20Poly1305>,
+}
+
+impl std::fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut key_ids: Vec<_> = self.keys.keys().collect();
+        key_ids.sort_unstable();
+        f.debug_struct("KeyRing")
+            .field("active", &self.active)
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+impl KeyRing {
+    /// Creates a key ring whose only key, `key_id`, is used for new records
+    pub fn new(key_id: u32, key: [u8; 32]) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(key_id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        KeyRing { active: key_id, keys }
+    }
+
+    /// Adds a key that can decrypt existing records but is not used for new ones
+    pub fn with_key(mut self, key_id: u32, key: [u8; 32]) -> Self {
+        self.keys.insert(key_id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        self
+    }
+
+    /// Adds a key and makes it the one used for new records
+    pub fn rotate(&mut self, key_id: u32, key: [u8; 32]) {
+        self.keys.insert(key_id, ChaCha20Poly1305::new(Key::from_slice(&key)));
+        self.active = key_id;
+    }
+
+    /// ID of the key used for new records
+    pub fn active_key_id(&self) -> u32 {
+        self.active
+    }
+
+    /// Encrypts `plaintext` with the active key, authenticating `aad` along with
+    /// it, as `<key id>:<base64 nonce and ciphertext>`
+    pub(crate) fn seal(&self, aad: &[u8], plaintext: &[u8]) -> String {
+        let cipher = &self.keys[&self.active];
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: plaintext, aad };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .expect("ChaCha20-Poly1305 encryption cannot fail for in-memory buffers");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        format!("{}:{}", self.active, BASE64.encode(sealed))
+    }
+
+    /// Decrypts a value produced by `seal` with the same `aad`
+    pub(crate) fn open(&self, aad: &[u8], sealed: &str) -> Result<Vec<u8>, String> {
+        let (key_id, encoded) = sealed
+            .split_once(':')
+            .ok_or_else(|| "malformed encrypted payload".to_string())?;
+        let key_id: u32 = key_id
+            .parse()
+            .map_err(|_| format!("invalid key id: {}", key_id))?;
+        let cipher = self
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| format!("no key with id {} in key ring", key_id))?;
+
+        let sealed = BASE64
+            .decode(encoded)
+            .map_err(|e| format!("invalid encrypted payload: {}", e))?;
+        if sealed.len() < NONCE_LEN {
+            return Err("encrypted payload too short".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| format!("authentication failed with key {}", key_id))
+    }
+}
+
+/// Associated data binding an encrypted field to the record it was written in
+fn field_aad(field: &str, timer_id: Uuid, seq: u64) -> Vec<u8> {
+    let mut aad = format!("SetTimer.{}:", field).into_bytes();
+    aad.extend_from_slice(timer_id.as_bytes());
+    aad.extend_from_slice(&seq.to_be_bytes());
+    aad
+}
+
+/// Store wrapper that encrypts timer payloads before they reach the inner store.
+///
+/// Only the `data` and `key` fields of `SetTimer` records are encrypted
+/// (ChaCha20-Poly1305); IDs, timestamps and expiry times stay readable so logs
+/// can still be inspected. Each field is authenticated against the field name,
+/// the timer ID and the sequence number of the record it was written in, so
+/// it cannot be moved to another field, timer or earlier record. A reschedule
+/// folded in by compaction gives the record a later sequence number, which is
+/// why later ones are accepted.
+///
+/// Unencrypted fields are refused unless `with_legacy_payloads` is set.
+/// Records that fail to decrypt are reported as corrupt.
+#[derive(Debug)]
+pub struct EncryptedStore<S> {
+    inner: S,
+    keys: KeyRing,
+    legacy_payloads: bool,
+}
+
+impl<S: TimerStore> EncryptedStore<S> {
+    pub fn new(inner: S, keys: KeyRing) -> Self {
+        EncryptedStore {
+            inner,
+            keys,
+            legacy_payloads: false,
+        }
+    }
+
+    /// Also reads fields written before encryption was enabled, or by versions
+    /// that did not bind them to their record, while a log is being migrated.
+    /// New records are always written fully encrypted.
+    pub fn with_legacy_payloads(mut self, accept: bool) -> Self {
+        self.legacy_payloads = accept;
+        self
+    }
+
+    /// The wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Decrypts records read straight from the inner log, such as those of
+    /// `OpLog::read_records`. Records that fail to decrypt come back as corrupt.
+    pub fn open_records(&self, records: Vec<LogRecord>) -> Vec<LogRecord> {
+        records
+            .into_iter()
+            .map(|record| match record {
+                LogRecord::Entry { location, mut entry } => match self.open_entry(&mut entry) {
+                    Ok(()) => LogRecord::Entry { location, entry },
+                    Err(corrupt) => LogRecord::Corrupt(CorruptRecord { location, ..corrupt }),
+                },
+                corrupt => corrupt,
+            })
+            .collect()
+    }
+
+    /// Encrypts the payload fields of a record that has been given its sequence number
+    fn seal_entry(&self, entry: &mut LogEntry) {
+        let seq = entry.seq;
+        if let LogOperation::SetTimer { timer_id, data, key, .. } = &mut entry.operation {
+            *data = self.seal_field("data", *timer_id, seq, data);
+            if let Some(key) = key {
+                *key = self.seal_field("key", *timer_id, seq, key);
+            }
+        }
+    }
+
+    /// Decrypts the payload fields of an entry read from the inner store
+    fn open_entry(&self, entry: &mut LogEntry) -> Result<(), CorruptRecord> {
+        let seq = entry.seq;
+        if let LogOperation::SetTimer { timer_id, data, key, .. } = &mut entry.operation {
+            let decrypted = self.open_field("data", *timer_id, seq, data).and_then(|data| {
+                let key = key
+                    .as_deref()
+                    .map(|key| self.open_field("key", *timer_id, seq, key))
+                    .transpose()?;
+                Ok((data, key))
+            });
+            match decrypted {
+                Ok(plaintext) => (*data, *key) = plaintext,
+                Err(error) => {
+                    return Err(CorruptRecord {
+                        location: format!("timer {} (seq {})", timer_id, seq),
+                        raw: data.clone(),
+                        error,
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn seal_field(&self, field: &str, timer_id: Uuid, seq: u64, plaintext: &str) -> String {
+        let sealed = self.keys.seal(&field_aad(field, timer_id, seq), plaintext.as_bytes());
+        format!("{}{}:{}", ENCRYPTED_PREFIX, seq, sealed)
+    }
+
+    /// Decrypts a field read from the record with sequence number `entry_seq`
+    fn open_field(&self, field: &str, timer_id: Uuid, entry_seq: u64, value: &str) -> Result<String, String> {
+        let plaintext = if let Some(sealed) = value.strip_prefix(ENCRYPTED_PREFIX) {
+            let (seq, sealed) = sealed
+                .split_once(':')
+                .ok_or_else(|| "malformed encrypted payload".to_string())?;
+            let seq: u64 = seq
+                .parse()
+                .map_err(|_| format!("invalid sequence number: {}", seq))?;
+            if seq > entry_seq {
+                return Err(format!("{} was encrypted for later record {}", field, seq));
+            }
+            self.keys.open(&field_aad(field, timer_id, seq), sealed)?
+        } else if !self.legacy_payloads {
+            return Err(format!("{} is not encrypted", field));
+        } else if let Some(sealed) = value.strip_prefix(LEGACY_PREFIX) {
+            self.keys.open(timer_id.as_bytes(), sealed)?
+        } else {
+            return Ok(value.to_string());
+        };
+        String::from_utf8(plaintext).map_err(|e| format!("decrypted {} is not UTF-8: {}", field, e))
+    }
+}
+
+impl<S: TimerStore> TimerStore for EncryptedStore<S> {
+    fn append(&self, entry: LogEntry) -> std::io::Result<u64> {
+        self.append_with(entry, &|_| {})
+    }
+
+    fn append_with(&self, entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        self.inner.append_with(entry, &|entry| {
+            self.seal_entry(entry);
+            prepare(entry);
+        })
+    }
+
+    fn read_all(&self) -> std::io::Result<LogContents> {
+        let mut contents = self.inner.read_all()?;
+        let mut entries = Vec::with_capacity(contents.entries.len());
+
+        for mut entry in contents.entries {
+            match self.open_entry(&mut entry) {
+                Ok(()) => entries.push(entry),
+                Err(corrupt) => contents.corrupt.push(corrupt),
+            }
+        }
+
+        contents.entries = entries;
+        Ok(contents)
+    }
+
+    fn compact(&self) -> std::io::Result<CompactionReport> {
+        self.inner.compact()
+    }
+
+    /// Repairs the inner store, then fails if any record still cannot be
+    /// decrypted. Such records are left in place rather than quarantined, since
+    /// the usual cause is a key missing from the ring.
+    fn repair(&self) -> std::io::Result<Vec<CorruptRecord>> {
+        let quarantined = self.inner.repair()?;
+        let undecryptable = self.read_all()?.corrupt;
+        if let Some(first) = undecryptable.first() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} records cannot be decrypted and were left in place, first {}: {}",
+                    undecryptable.len(),
+                    first.location,
+                    first.error
+                ),
+            ));
+        }
+        Ok(quarantined)
+    }
+
+    /// Backs up the inner store; payloads stay encrypted in the copy
+    fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport> {
+        self.inner.backup_to(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::MemoryStore;
+
+    fn set_entry(timer_id: Uuid, data: &str) -> LogEntry {
+        LogEntry::new(
+            1,
+            LogOperation::SetTimer {
+                timer_id,
+                expires_at: 1000,
+                data: data.to_string(),
+                created_at: 0,
+                duration_ms: 1000,
+                priority: 0,
+                uptime: false,
+                key: None,
+            },
+        )
+    }
+
+    fn stored_data(store: &MemoryStore) -> Vec<String> {
+        store
+            .read_all()
+            .unwrap()
+            .entries
+            .into_iter()
+            .filter_map(|entry| match entry.operation {
+                LogOperation::SetTimer { data, .. } => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_payloads_encrypted_and_readable_after_rotation() {
+        let inner = MemoryStore::new();
+        let mut keys = KeyRing::new(1, [7; 32]);
+        EncryptedStore::new(inner.clone(), keys.clone())
+            .append(set_entry(Uuid::new_v4(), "alice@example.com"))
+            .unwrap();
+
+        keys.rotate(2, [9; 32]);
+        let store = EncryptedStore::new(inner.clone(), keys);
+        store.append(set_entry(Uuid::new_v4(), "bob@example.com")).unwrap();
+
+        assert!(stored_data(&inner).iter().all(|data| !data.contains("example.com")));
+        let contents = store.read_all().unwrap();
+        assert!(contents.corrupt.is_empty());
+        assert_eq!(contents.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_wrong_key_reported_as_corrupt() {
+        let inner = MemoryStore::new();
+        EncryptedStore::new(inner.clone(), KeyRing::new(1, [7; 32]))
+            .append(set_entry(Uuid::new_v4(), "secret"))
+            .unwrap();
+
+        let store = EncryptedStore::new(inner, KeyRing::new(1, [8; 32]));
+        let contents = store.read_all().unwrap();
+        assert!(contents.entries.is_empty());
+        assert_eq!(contents.corrupt.len(), 1);
+        assert_eq!(store.repair().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_payloads_bound_to_their_record_and_plaintext_refused() {
+        let inner = MemoryStore::new();
+        let keys = KeyRing::new(1, [7; 32]);
+        let store = EncryptedStore::new(inner.clone(), keys.clone());
+        let timer_id = Uuid::new_v4();
+        store.append(set_entry(timer_id, "first")).unwrap();
+        store.append(set_entry(timer_id, "second")).unwrap();
+        inner.append(set_entry(Uuid::new_v4(), "written before encryption")).unwrap();
+
+        // Swap the two ciphertexts; only the one moved to a later record still opens
+        let stored = stored_data(&inner);
+        let tampered = MemoryStore::new();
+        for data in [stored[1].clone(), stored[0].clone()] {
+            let mut entry = set_entry(timer_id, "");
+            if let LogOperation::SetTimer { data: field, .. } = &mut entry.operation {
+                *field = data;
+            }
+            tampered.append(entry).unwrap();
+        }
+        let contents = EncryptedStore::new(tampered, keys.clone()).read_all().unwrap();
+        assert_eq!((contents.entries.len(), contents.corrupt.len()), (1, 1));
+
+        let strict = EncryptedStore::new(inner.clone(), keys.clone()).read_all().unwrap();
+        assert_eq!((strict.entries.len(), strict.corrupt.len()), (2, 1));
+        let migrating = EncryptedStore::new(inner, keys).with_legacy_payloads(true);
+        assert_eq!(migrating.read_all().unwrap().entries.len(), 3);
+    }
+}
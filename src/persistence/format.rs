@@ -0,0 +1,163 @@
+use super::LogEntry;
+use serde_json::Value;
+
+/// Version of the log record format written by this build
+pub const LOG_FORMAT_VERSION: u32 = 1;
+
+/// Errors that can occur while decoding a log record
+#[derive(Debug)]
+pub enum FormatError {
+    InvalidRecord(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::InvalidRecord(e) => write!(f, "Invalid log record: {}", e),
+            FormatError::UnsupportedVersion(v) => write!(
+                f,
+                "Unsupported log format version {} (this build reads up to {})",
+                v, LOG_FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<serde_json::Error> for FormatError {
+    fn from(err: serde_json::Error) -> Self {
+        FormatError::InvalidRecord(err)
+    }
+}
+
+impl From<FormatError> for std::io::Error {
+    fn from(err: FormatError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Serializes a log entry into a single line in the current format
+pub fn encode_entry(entry: &LogEntry) -> serde_json::Result<String> {
+    serde_json::to_string(entry)
+}
+
+/// Decodes a log line written by this or any older version of the format
+pub fn decode_entry(line: &str) -> Result<LogEntry, FormatError> {
+    decode_record(line).map(|(entry, _)| entry)
+}
+
+/// Decodes a log line and also returns the format version it was written with
+pub fn decode_record(line: &str) -> Result<(LogEntry, u32), FormatError> {
+    let value: Value = serde_json::from_str(line)?;
+    let version = record_version(&value);
+
+    if version > LOG_FORMAT_VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+
+    let entry = serde_json::from_value(upgrade(value, version))?;
+    Ok((entry, version))
+}
+
+/// Reads the format version of a raw record; records without one predate versioning
+fn record_version(value: &Value) -> u32 {
+    value
+        .get("version")
+        .and_then(Value::as_u64)
+        .map(|v| v as u32)
+        .unwrap_or(0)
+}
+
+/// Applies each migration step from `version` up to the current format
+fn upgrade(mut value: Value, version: u32) -> Value {
+    if version < 1 {
+        value = upgrade_v0_to_v1(value);
+    }
+    value
+}
+
+/// v1 adds `version` and `seq` to every record, and `created_at`,
+/// `duration_ms`, `priority`, `uptime` and `key` to `SetTimer`. Unversioned
+/// timers are assumed to have been set at the entry's timestamp, with
+/// priority 0, wall-clock time and no key; their `seq` stays 0.
+fn upgrade_v0_to_v1(mut value: Value) -> Value {
+    let timestamp = value.get("timestamp").and_then(Value::as_u64).unwrap_or(0);
+    if let Value::Object(ref mut map) = value {
+        map.insert("version".to_string(), Value::from(1));
+        if let Some(Value::Object(set)) = map
+            .get_mut("operation")
+            .and_then(|operation| operation.get_mut("SetTimer"))
+        {
+            let expires_at = set.get("expires_at").and_then(Value::as_u64).unwrap_or(timestamp);
+            set.entry("created_at").or_insert(Value::from(timestamp));
+            set.entry("duration_ms")
+                .or_insert(Value::from(expires_at.saturating_sub(timestamp)));
+            set.entry("priority").or_insert(Value::from(0));
+            set.entry("uptime").or_insert(Value::from(false));
+            set.entry("key").or_insert(Value::Null);
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::LogOperation;
+
+    #[test]
+    fn test_decode_unversioned_record() {
+        let line = r#"{"timestamp":10,"operation":{"RemoveTimer":{"timer_id":"67e55044-10b1-426f-9247-bb680e5fe0c8"}}}"#;
+        let (entry, version) = decode_record(line).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(entry.version, LOG_FORMAT_VERSION);
+        assert_eq!(entry.timestamp, 10);
+        assert!(matches!(entry.operation, LogOperation::RemoveTimer { .. }));
+    }
+
+    #[test]
+    fn test_round_trip_current_version() {
+        let entry = LogEntry::new(
+            42,
+            LogOperation::SetTimer {
+                timer_id: uuid::Uuid::new_v4(),
+                expires_at: 1000,
+                data: "payload".to_string(),
+                created_at: 40,
+                duration_ms: 960,
+                priority: 0,
+                uptime: false,
+                key: None,
+            },
+        );
+        let line = encode_entry(&entry).unwrap();
+        let (decoded, version) = decode_record(&line).unwrap();
+        assert_eq!(version, LOG_FORMAT_VERSION);
+        assert_eq!(decoded.timestamp, 42);
+    }
+
+    #[test]
+    fn test_upgrade_unversioned_set_timer() {
+        let line = r#"{"timestamp":100,"operation":{"SetTimer":{"timer_id":"67e55044-10b1-426f-9247-bb680e5fe0c8","expires_at":350,"data":"a"}}}"#;
+        let entry = decode_entry(line).unwrap();
+        assert_eq!(entry.version, LOG_FORMAT_VERSION);
+        assert!(matches!(
+            entry.operation,
+            LogOperation::SetTimer { created_at: 100, duration_ms: 250, priority: 0, uptime: false, key: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_reject_future_version() {
+        let line = format!(
+            r#"{{"version":{},"timestamp":1,"operation":{{"Unknown":{{}}}}}}"#,
+            LOG_FORMAT_VERSION + 1
+        );
+        assert!(matches!(
+            decode_record(&line),
+            Err(FormatError::UnsupportedVersion(_))
+        ));
+    }
+}
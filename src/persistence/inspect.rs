@@ -0,0 +1,234 @@
+use super::{CorruptRecord, LogOperation, LogRecord, OpLog};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// A consistency problem found while verifying a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogIssue {
+    /// A `RemoveTimer` or `FireTimer` for an ID that was never set, or was already removed
+    UnknownRemoval { location: String, timer_id: Uuid },
+    /// A `SetTimer` for an ID that is already live
+    DuplicateSet { location: String, timer_id: Uuid },
+    /// A `SetTimer` reusing the ID of a timer that was removed earlier
+    ReusedId { location: String, timer_id: Uuid },
+    /// A sequence number no higher than the one before it
+    SeqOutOfOrder { location: String, seq: u64, previous: u64 },
+    /// A `RescheduleTimer` for an ID that is not live
+    UnknownReschedule { location: String, timer_id: Uuid },
+}
+
+impl std::fmt::Display for LogIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogIssue::UnknownRemoval { location, timer_id } => {
+                write!(f, "{}: remove of unknown timer {}", location, timer_id)
+            }
+            LogIssue::DuplicateSet { location, timer_id } => {
+                write!(f, "{}: duplicate set of live timer {}", location, timer_id)
+            }
+            LogIssue::ReusedId { location, timer_id } => {
+                write!(f, "{}: set reuses ID of removed timer {}", location, timer_id)
+            }
+            LogIssue::SeqOutOfOrder { location, seq, previous } => {
+                write!(f, "{}: sequence number {} follows {}", location, seq, previous)
+            }
+            LogIssue::UnknownReschedule { location, timer_id } => {
+                write!(f, "{}: reschedule of unknown timer {}", location, timer_id)
+            }
+        }
+    }
+}
+
+/// Result of verifying a log
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Decodable entries checked
+    pub entries: usize,
+    /// Records that could not be decoded
+    pub corrupt: Vec<CorruptRecord>,
+    /// Consistency problems among the decodable entries
+    pub issues: Vec<LogIssue>,
+}
+
+impl VerifyReport {
+    /// Whether every record decoded and no consistency problems were found
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty() && self.issues.is_empty()
+    }
+}
+
+/// Summary statistics of a log
+#[derive(Debug, Clone, Default)]
+pub struct LogStats {
+    pub total_entries: usize,
+    pub set_entries: usize,
+    pub remove_entries: usize,
+    pub fire_entries: usize,
+    pub reschedule_entries: usize,
+    pub checkpoint_entries: usize,
+    pub shift_entries: usize,
+    pub corrupt_records: usize,
+    /// Timers set and not removed
+    pub live_timers: usize,
+    /// Timers set and later removed (cancelled or fired)
+    pub removed_timers: usize,
+    /// Files making up the log
+    pub segments: usize,
+    pub size_bytes: u64,
+    /// Timestamp of the first and last entry
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    /// The live timer that was set longest ago, with the time it was set
+    pub oldest_live: Option<(Uuid, u64)>,
+    /// Latest time whose history was discarded by compaction
+    pub compacted_through: Option<u64>,
+}
+
+/// Checks that every record decodes and that the operations are consistent
+pub fn verify_log(log: &OpLog) -> std::io::Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut live = HashSet::new();
+    let mut removed = HashSet::new();
+    let mut previous_seq = 0;
+
+    for record in log.read_records()? {
+        let (location, entry) = match record {
+            LogRecord::Entry { location, entry } => (location, entry),
+            LogRecord::Corrupt(corrupt) => {
+                report.corrupt.push(corrupt);
+                continue;
+            }
+        };
+        report.entries += 1;
+
+        // Unversioned records have no sequence number
+        if entry.seq != 0 {
+            if entry.seq <= previous_seq {
+                report.issues.push(LogIssue::SeqOutOfOrder {
+                    location: location.clone(),
+                    seq: entry.seq,
+                    previous: previous_seq,
+                });
+            }
+            previous_seq = entry.seq;
+        }
+
+        match entry.operation {
+            LogOperation::SetTimer { timer_id, .. } => {
+                if !live.insert(timer_id) {
+                    report.issues.push(LogIssue::DuplicateSet { location, timer_id });
+                } else if removed.remove(&timer_id) {
+                    report.issues.push(LogIssue::ReusedId { location, timer_id });
+                }
+            }
+            LogOperation::RemoveTimer { timer_id } | LogOperation::FireTimer { timer_id } => {
+                if live.remove(&timer_id) {
+                    removed.insert(timer_id);
+                } else {
+                    report.issues.push(LogIssue::UnknownRemoval { location, timer_id });
+                }
+            }
+            LogOperation::RescheduleTimer { timer_id, .. } => {
+                if !live.contains(&timer_id) {
+                    report.issues.push(LogIssue::UnknownReschedule { location, timer_id });
+                }
+            }
+            LogOperation::Checkpoint | LogOperation::ShiftDeadlines { .. } | LogOperation::Compacted => {}
+        }
+    }
+
+    Ok(report)
+}
+
+/// Computes summary statistics of a log
+pub fn log_stats(log: &OpLog) -> std::io::Result<LogStats> {
+    let mut stats = LogStats {
+        segments: log.segment_paths()?.len(),
+        size_bytes: log.size_bytes()?,
+        ..Default::default()
+    };
+    let mut live: HashMap<Uuid, u64> = HashMap::new();
+    let mut removed = HashSet::new();
+
+    for record in log.read_records()? {
+        let entry = match record {
+            LogRecord::Entry { entry, .. } => entry,
+            LogRecord::Corrupt(_) => {
+                stats.corrupt_records += 1;
+                continue;
+            }
+        };
+        stats.total_entries += 1;
+        stats.first_timestamp = Some(stats.first_timestamp.map_or(entry.timestamp, |t| t.min(entry.timestamp)));
+        stats.last_timestamp = Some(stats.last_timestamp.map_or(entry.timestamp, |t| t.max(entry.timestamp)));
+
+        match entry.operation {
+            LogOperation::SetTimer { timer_id, .. } => {
+                stats.set_entries += 1;
+                live.entry(timer_id).or_insert(entry.timestamp);
+                removed.remove(&timer_id);
+            }
+            LogOperation::RemoveTimer { timer_id } => {
+                stats.remove_entries += 1;
+                if live.remove(&timer_id).is_some() {
+                    removed.insert(timer_id);
+                }
+            }
+            LogOperation::FireTimer { timer_id } => {
+                stats.fire_entries += 1;
+                if live.remove(&timer_id).is_some() {
+                    removed.insert(timer_id);
+                }
+            }
+            LogOperation::RescheduleTimer { .. } => stats.reschedule_entries += 1,
+            LogOperation::Checkpoint => stats.checkpoint_entries += 1,
+            LogOperation::ShiftDeadlines { .. } => stats.shift_entries += 1,
+            LogOperation::Compacted => stats.compacted_through = Some(entry.timestamp),
+        }
+    }
+
+    stats.live_timers = live.len();
+    stats.removed_timers = removed.len();
+    stats.oldest_live = live.into_iter().min_by_key(|(_, set_at)| *set_at);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::LogEntry;
+
+    #[test]
+    fn test_verify_reports_inconsistencies() {
+        let path = std::env::temp_dir().join(format!("timer_ship_verify_{}.log", Uuid::new_v4()));
+        let log = OpLog::new(path.to_str().unwrap()).unwrap();
+        let id = Uuid::new_v4();
+        let set = LogOperation::SetTimer {
+            timer_id: id,
+            expires_at: 1000,
+            data: "x".to_string(),
+            created_at: 0,
+            duration_ms: 1000,
+            priority: 0,
+            uptime: false,
+            key: None,
+        };
+        log.append_log(LogEntry::new(1, set.clone())).unwrap();
+        log.append_log(LogEntry::new(2, set)).unwrap();
+        log.append_log(LogEntry::new(3, LogOperation::RemoveTimer { timer_id: id })).unwrap();
+        log.append_log(LogEntry::new(4, LogOperation::RemoveTimer { timer_id: id })).unwrap();
+
+        let report = verify_log(&log).unwrap();
+        assert!(!report.is_ok());
+        assert!(matches!(report.issues[0], LogIssue::DuplicateSet { .. }));
+        assert!(matches!(report.issues[1], LogIssue::UnknownRemoval { .. }));
+
+        let stats = log_stats(&log).unwrap();
+        assert_eq!(stats.total_entries, 4);
+        assert_eq!(stats.live_timers, 0);
+        assert_eq!(stats.removed_timers, 1);
+        drop(log);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("log.lock")).unwrap();
+    }
+}
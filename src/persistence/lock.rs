@@ -0,0 +1,60 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a waiting open retries the lock
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Kind of advisory lock held on a log path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockMode {
+    /// Held by the single writer; excludes every other holder
+    Exclusive,
+    /// Held by read-only openers; excludes only a writer
+    Shared,
+}
+
+/// Advisory lock on a log path, released when dropped.
+///
+/// The lock is taken on a sidecar file (`<log>.lock`, or `LOCK` inside a
+/// segment directory) which is left in place afterwards: removing it could
+/// let two processes lock different files for the same log.
+#[derive(Debug)]
+pub(crate) struct LogLock {
+    _file: File,
+}
+
+impl LogLock {
+    /// Takes the lock at `path`, waiting up to `timeout` if another holder has it.
+    ///
+    /// Fails with `ErrorKind::ResourceBusy` if the lock is still unavailable.
+    pub(crate) fn acquire(path: &Path, mode: LockMode, timeout: Option<Duration>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let result = match mode {
+                LockMode::Exclusive => file.try_lock(),
+                LockMode::Shared => file.try_lock_shared(),
+            };
+            match result {
+                Ok(()) => return Ok(LogLock { _file: file }),
+                Err(TryLockError::Error(e)) => return Err(e),
+                Err(TryLockError::WouldBlock) => match deadline {
+                    Some(deadline) if Instant::now() < deadline => thread::sleep(LOCK_RETRY_INTERVAL),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ResourceBusy,
+                            format!("log is in use by another process or instance (lock held on {})", path.display()),
+                        ))
+                    }
+                },
+            }
+        }
+    }
+}
+
@@ -0,0 +1,132 @@
+use super::format::{decode_record, encode_entry, FormatError, LOG_FORMAT_VERSION};
+use super::segment::{is_compressed, open_reader, write_lines};
+use super::OpLog;
+use log::{info, warn};
+use std::{fs, io::BufRead, path::Path};
+
+/// Summary of a log migration
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    /// Records rewritten from an older format version
+    pub upgraded: usize,
+    /// Records that were already in the current format
+    pub current: usize,
+    /// Lines that could not be decoded and were kept as-is
+    pub unreadable: usize,
+}
+
+impl MigrationReport {
+    /// Total number of records written to the migrated log
+    pub fn total(&self) -> usize {
+        self.upgraded + self.current + self.unreadable
+    }
+}
+
+/// Rewrites the log at `log_path` so every record uses the current format version.
+///
+/// Each file is written to a temporary file and renamed over the original, so a
+/// crash midway leaves that file untouched. Lines that cannot be decoded are
+/// carried over verbatim rather than dropped. `log_path` may be a single log
+/// file or a segment directory. The log is locked for the duration, so this
+/// fails if a running `TimerShip` has it open.
+pub fn migrate_log(log_path: &str) -> std::io::Result<MigrationReport> {
+    let log = OpLog::open_existing(log_path)?;
+    let mut report = MigrationReport::default();
+    for path in log.segment_paths()? {
+        let file_report = migrate_file(&path)?;
+        report.upgraded += file_report.upgraded;
+        report.current += file_report.current;
+        report.unreadable += file_report.unreadable;
+    }
+
+    info!(
+        "Migrated {} to format version {}: {} upgraded, {} current, {} unreadable",
+        log_path,
+        LOG_FORMAT_VERSION,
+        report.upgraded,
+        report.current,
+        report.unreadable
+    );
+    Ok(report)
+}
+
+/// Migrates a single log file in place
+fn migrate_file(log_path: &Path) -> std::io::Result<MigrationReport> {
+    let mut lines = Vec::new();
+    let mut report = MigrationReport::default();
+
+    for (line_no, line) in open_reader(log_path)?.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match decode_record(&line) {
+            Ok((entry, version)) => {
+                if version < LOG_FORMAT_VERSION {
+                    report.upgraded += 1;
+                } else {
+                    report.current += 1;
+                }
+                lines.push(encode_entry(&entry)?);
+            }
+            Err(FormatError::UnsupportedVersion(version)) => {
+                return Err(FormatError::UnsupportedVersion(version).into());
+            }
+            Err(e) => {
+                warn!("Keeping undecodable record at line {}: {}", line_no + 1, e);
+                report.unreadable += 1;
+                lines.push(line);
+            }
+        }
+    }
+
+    let tmp_path = log_path.with_extension("migrate.tmp");
+    let tmp_file = TempFile(&tmp_path);
+    write_lines(&tmp_path, is_compressed(log_path), &lines)?;
+    fs::rename(&tmp_path, log_path)?;
+    std::mem::forget(tmp_file);
+    Ok(report)
+}
+
+/// Deletes a temporary file when dropped, so a failed migration leaves nothing behind
+struct TempFile<'a>(&'a Path);
+
+impl Drop for TempFile<'_> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::format::decode_record;
+
+    #[test]
+    fn test_migrate_unversioned_log() {
+        let path = std::env::temp_dir().join(format!("timer_ship_migrate_{}.log", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let lock_path = format!("{}.lock", path);
+        // Removed even if an assertion fails
+        let _cleanup = [TempFile(Path::new(&path)), TempFile(Path::new(&lock_path))];
+        fs::write(
+            &path,
+            concat!(
+                r#"{"timestamp":1,"operation":{"SetTimer":{"timer_id":"67e55044-10b1-426f-9247-bb680e5fe0c8","expires_at":5,"data":"a"}}}"#,
+                "\n",
+                "not json\n",
+            ),
+        )
+        .unwrap();
+
+        let report = migrate_log(&path).unwrap();
+        assert_eq!(report.upgraded, 1);
+        assert_eq!(report.unreadable, 1);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let first = contents.lines().next().unwrap();
+        assert_eq!(decode_record(first).unwrap().1, LOG_FORMAT_VERSION);
+        assert!(!Path::new(&path).with_extension("migrate.tmp").exists());
+    }
+}
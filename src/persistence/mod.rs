@@ -1,5 +1,26 @@
+pub mod backup;
+pub mod directory_store;
+pub mod encryption;
+pub mod format;
+pub mod inspect;
+mod lock;
+pub mod migrate;
 pub mod oplog;
 pub mod recovery;
+mod segment;
+pub mod sharded_store;
+pub mod store;
 
-pub use oplog::{OpLog, LogEntry, LogOperation};
-pub use recovery::RecoveryManager;
+pub use backup::{restore_backup, BackupReport};
+pub use directory_store::DirectoryStore;
+pub use encryption::{EncryptedStore, KeyRing};
+pub use format::{FormatError, LOG_FORMAT_VERSION};
+pub use inspect::{log_stats, verify_log, LogIssue, LogStats, VerifyReport};
+pub use migrate::{migrate_log, MigrationReport};
+pub use oplog::{
+    CompactionReport, CorruptRecord, LogContents, LogRecord, OpLog, LogEntry, LogOperation,
+    DEFAULT_SEGMENT_SIZE,
+};
+pub use recovery::{MissedTimerPolicy, RecoveryManager, RecoveryPolicy, RecoveryReport, RecoveryTarget};
+pub use sharded_store::ShardedStore;
+pub use store::{MemoryStore, TimerStore};
@@ -1,78 +1,1174 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Write},
-    sync::{Arc, Mutex},
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
 };
 use uuid::Uuid;
-use log::warn;
+use log::{info, warn};
+use super::backup::{copy_file, BackupReport};
+use super::format::{decode_entry, encode_entry, LOG_FORMAT_VERSION};
+use super::lock::{LockMode, LogLock};
+use super::segment::{
+    compress_segment, compressed_segment_path, is_compressed, last_seq, open_reader, report_compression_error,
+    segment_files, segment_path, write_lines,
+};
+
+/// Default size at which a log segment is sealed and a new one started
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How many entries of the file being appended to share one offset in the sequence index
+const INDEX_INTERVAL: u64 = 64;
 
 /// Represents different timer operations that can be logged
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum LogOperation {
     SetTimer {
         timer_id: Uuid,
         expires_at: u64,
         data: String,
+        /// When the timer was set, in milliseconds since UNIX epoch
+        created_at: u64,
+        /// Duration the timer was originally set for, kept through reschedules and shifts
+        duration_ms: u64,
+        /// Orders the timer among timers due in the same millisecond, highest first
+        priority: u8,
+        /// The deadline moves forward by any downtime seen at recovery
+        uptime: bool,
+        /// Groups timers for `MissedTimerPolicy::FireLatestPerKey`
+        key: Option<String>,
     },
     RemoveTimer {
         timer_id: Uuid,
     },
+    /// A timer expired and was removed by the timer thread
+    FireTimer {
+        timer_id: Uuid,
+    },
+    /// A live timer was moved to a new expiration time
+    RescheduleTimer {
+        timer_id: Uuid,
+        expires_at: u64,
+    },
+    /// The process was running at the entry's timestamp, written while uptime
+    /// timers are pending and on shutdown
+    Checkpoint,
+    /// Every live timer's expiration time moved by `offset_ms` after a wall
+    /// clock jump
+    ShiftDeadlines {
+        offset_ms: i64,
+    },
+    /// Written first by compaction; history up to the entry's timestamp was
+    /// discarded, so earlier states can no longer be replayed
+    Compacted,
+}
+
+impl LogOperation {
+    /// ID of the timer the operation applies to, if it applies to one
+    pub fn timer_id(&self) -> Option<Uuid> {
+        match self {
+            LogOperation::SetTimer { timer_id, .. }
+            | LogOperation::RemoveTimer { timer_id }
+            | LogOperation::FireTimer { timer_id }
+            | LogOperation::RescheduleTimer { timer_id, .. } => Some(*timer_id),
+            LogOperation::Checkpoint | LogOperation::ShiftDeadlines { .. } | LogOperation::Compacted => None,
+        }
+    }
 }
 
 /// A log entry containing timestamp and operation
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LogEntry {
+    /// Format version the entry was written with (0 for unversioned records)
+    #[serde(default)]
+    pub version: u32,
+    /// Position in the store's change stream, assigned when the entry is
+    /// appended. Strictly increasing; 0 for unversioned records.
+    #[serde(default)]
+    pub seq: u64,
     pub timestamp: u64,
     pub operation: LogOperation,
 }
 
-/// Persistent operation log for timer operations
+impl LogEntry {
+    /// Creates a log entry in the current format version.
+    ///
+    /// `seq` is left at 0 until the entry is appended to a store.
+    pub fn new(timestamp: u64, operation: LogOperation) -> Self {
+        LogEntry {
+            version: LOG_FORMAT_VERSION,
+            seq: 0,
+            timestamp,
+            operation,
+        }
+    }
+}
+
+/// A log record that could not be decoded
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CorruptRecord {
+    /// Where the record was found, e.g. `file:line`
+    pub location: String,
+    /// The raw record as stored
+    pub raw: String,
+    /// Why it could not be decoded
+    pub error: String,
+}
+
+/// A single record of an operation log, in file order
+#[derive(Debug, Clone)]
+pub enum LogRecord {
+    /// A decodable entry and where it was found, e.g. `file:line`
+    Entry { location: String, entry: LogEntry },
+    /// A record that could not be decoded
+    Corrupt(CorruptRecord),
+}
+
+/// Everything read back from a store: decodable entries plus records that failed to decode
+#[derive(Debug, Clone, Default)]
+pub struct LogContents {
+    pub entries: Vec<LogEntry>,
+    pub corrupt: Vec<CorruptRecord>,
+}
+
+/// Result of compacting an operation log
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    /// Entries in the log before compaction
+    pub entries_before: usize,
+    /// Entries kept after compaction (one per live timer)
+    pub entries_after: usize,
+    /// Sealed segments deleted because none of their timers were live
+    pub segments_removed: usize,
+}
+
+/// How the log is laid out on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogLayout {
+    /// A single append-only file
+    File,
+    /// A directory of numbered segments that roll over at `segment_size` bytes
+    Segmented { segment_size: u64 },
+}
+
+/// The file currently being appended to
+#[derive(Debug)]
+struct LogWriter {
+    file: BufWriter<File>,
+    segment: u64,
+    size: u64,
+    /// Sequence number given to the next appended entry
+    next_seq: u64,
+    /// Built on the first `changes_since` and dropped whenever files are rewritten
+    index: Option<SeqIndex>,
+}
+
+/// Where entries are by sequence number, so `changes_since` reads only the tail of the log
+#[derive(Debug, Default)]
+struct SeqIndex {
+    /// Highest sequence number in each sealed segment
+    sealed: BTreeMap<u64, u64>,
+    /// Sequence number and byte offset of every `INDEX_INTERVAL`th entry in the file being appended to
+    active: Vec<(u64, u64)>,
+    /// Entries appended since the last indexed one
+    unindexed: u64,
+}
+
+impl SeqIndex {
+    fn record(&mut self, seq: u64, offset: u64) {
+        if self.unindexed == 0 {
+            self.active.push((seq, offset));
+        }
+        self.unindexed = (self.unindexed + 1) % INDEX_INTERVAL;
+    }
+}
+
+/// Persistent operation log for timer operations.
+///
+/// Opening a log takes an advisory lock on it: an exclusive one for a writable
+/// log, or a shared one for a read-only log. The lock is released once the
+/// last clone of the `OpLog` is dropped.
 #[derive(Debug, Clone)]
 pub struct OpLog {
-    file: Arc<Mutex<BufWriter<File>>>,
+    /// `None` when the log was opened read-only
+    writer: Option<Arc<Mutex<LogWriter>>>,
     log_path: String,
+    layout: LogLayout,
+    compress_sealed: bool,
+    /// Held while segment files are rewritten, compressed or deleted
+    maintenance: Arc<Mutex<()>>,
+    _lock: Arc<LogLock>,
 }
 
 impl OpLog {
-    /// Creates a new operation log at the specified path
+    /// Creates a new operation log at the specified path.
+    ///
+    /// If `log_path` is an existing directory, or ends with a path separator,
+    /// the log is stored as numbered segments inside that directory. Otherwise
+    /// it is a single file, as in earlier versions.
+    ///
+    /// Fails with `ErrorKind::ResourceBusy` if another `OpLog`, in this or
+    /// another process, already has the log open.
     pub fn new(log_path: &str) -> std::io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?;
+        Self::open_writable(log_path, None)
+    }
+
+    /// Like `new`, but waits up to `timeout` for another holder to release the log
+    pub fn open_with_timeout(log_path: &str, timeout: Duration) -> std::io::Result<Self> {
+        Self::open_writable(log_path, Some(timeout))
+    }
+
+    /// Opens an existing log file or segment directory, failing if it does not exist
+    pub fn open_existing(log_path: &str) -> std::io::Result<Self> {
+        ensure_exists(log_path)?;
+        Self::new(log_path)
+    }
+
+    /// Opens an existing log for reading only.
+    ///
+    /// Takes a shared lock, so any number of readers can open the log at once
+    /// but not while a writer has it open. Appending, compacting or repairing
+    /// a read-only log fails with `ErrorKind::PermissionDenied`.
+    pub fn open_read_only(log_path: &str) -> std::io::Result<Self> {
+        ensure_exists(log_path)?;
+        let layout = if Path::new(log_path).is_dir() {
+            LogLayout::Segmented { segment_size: DEFAULT_SEGMENT_SIZE }
+        } else {
+            LogLayout::File
+        };
+        let lock = LogLock::acquire(&lock_path(log_path, layout), LockMode::Shared, None)?;
 
         Ok(OpLog {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
+            writer: None,
             log_path: log_path.to_string(),
+            layout,
+            compress_sealed: false,
+            maintenance: Arc::new(Mutex::new(())),
+            _lock: Arc::new(lock),
         })
     }
 
-    /// Appends a log entry to the operation log
-    pub fn append_log(&self, entry: LogEntry) -> std::io::Result<()> {
-        let mut file = self.file.lock().expect("Failed to lock log file");
-        let serialized = serde_json::to_string(&entry)?;
-        writeln!(file, "{}", serialized)?;
-        file.flush()?;
-        drop(file);
-        Ok(())
+    /// Takes the exclusive lock on a log without opening a file to append to,
+    /// so the log's files can be replaced while nothing else has it open
+    pub(crate) fn lock_exclusive(log_path: &str, segmented: bool) -> std::io::Result<Self> {
+        let layout = if segmented {
+            fs::create_dir_all(log_path)?;
+            LogLayout::Segmented { segment_size: DEFAULT_SEGMENT_SIZE }
+        } else {
+            LogLayout::File
+        };
+        let lock = LogLock::acquire(&lock_path(log_path, layout), LockMode::Exclusive, None)?;
+
+        Ok(OpLog {
+            writer: None,
+            log_path: log_path.to_string(),
+            layout,
+            compress_sealed: false,
+            maintenance: Arc::new(Mutex::new(())),
+            _lock: Arc::new(lock),
+        })
     }
 
-    /// Reads all log entries from the operation log
-    pub fn read_logs(&self) -> std::io::Result<Vec<LogEntry>> {
-        let file = File::open(&self.log_path)?;
-        let reader = BufReader::new(file);
-        let mut entries = Vec::new();
+    /// Creates a segmented operation log in `dir`, rolling segments at `segment_size` bytes
+    pub fn segmented(dir: &str, segment_size: u64) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Self::open(dir, LogLayout::Segmented { segment_size }, None)
+    }
 
-        for line in reader.lines() {
-            let line = line?;
-            if !line.trim().is_empty() {
-                match serde_json::from_str::<LogEntry>(&line) {
-                    Ok(entry) => entries.push(entry),
-                    Err(e) => warn!("Failed to deserialize log entry: {}", e),
+    fn open_writable(log_path: &str, timeout: Option<Duration>) -> std::io::Result<Self> {
+        if Path::new(log_path).is_dir() || log_path.ends_with('/') || log_path.ends_with('\\') {
+            fs::create_dir_all(log_path)?;
+            Self::open(log_path, LogLayout::Segmented { segment_size: DEFAULT_SEGMENT_SIZE }, timeout)
+        } else {
+            Self::open(log_path, LogLayout::File, timeout)
+        }
+    }
+
+    fn open(log_path: &str, layout: LogLayout, timeout: Option<Duration>) -> std::io::Result<Self> {
+        // Lock before looking at the files so a concurrent writer cannot move them underneath us
+        let lock = LogLock::acquire(&lock_path(log_path, layout), LockMode::Exclusive, timeout)?;
+
+        let segment = match layout {
+            LogLayout::File => 0,
+            LogLayout::Segmented { .. } => match segment_files(Path::new(log_path))?.last() {
+                // A compressed segment is sealed, so appends go to the next one
+                Some((number, path)) if is_compressed(path) => number + 1,
+                Some((number, _)) => *number,
+                None => 1,
+            },
+        };
+
+        let mut writer = LogWriter::open(&writer_path(log_path, layout, segment), segment)?;
+        writer.next_seq = next_seq(log_path, layout)?;
+
+        Ok(OpLog {
+            writer: Some(Arc::new(Mutex::new(writer))),
+            log_path: log_path.to_string(),
+            layout,
+            compress_sealed: false,
+            maintenance: Arc::new(Mutex::new(())),
+            _lock: Arc::new(lock),
+        })
+    }
+
+    /// Enables gzip compression of segments once they are sealed.
+    ///
+    /// Segments are compressed on a background thread when the log rolls over,
+    /// and compaction output is written compressed. Compressed segments are
+    /// decompressed transparently when read. Has no effect on single-file logs.
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compress_sealed = enabled;
+        self
+    }
+
+    /// Compresses every sealed segment that is not yet compressed and returns how many were.
+    ///
+    /// Rolling over already does this in the background; calling it directly is
+    /// useful to compress segments left behind by a run without compression.
+    pub fn compress_sealed_segments(&self) -> std::io::Result<usize> {
+        if !self.is_segmented() {
+            return Ok(0);
+        }
+
+        let active = self.lock_writer()?.segment;
+        let _maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+        let dir = Path::new(&self.log_path);
+        let mut compressed = 0;
+
+        for (segment, path) in segment_files(dir)? {
+            if segment < active && !is_compressed(&path) {
+                compress_segment(dir, segment)?;
+                compressed += 1;
+            }
+        }
+        Ok(compressed)
+    }
+
+    /// Path of the log file or segment directory
+    pub fn path(&self) -> &str {
+        &self.log_path
+    }
+
+    /// Whether the log is stored as a directory of segments
+    pub fn is_segmented(&self) -> bool {
+        matches!(self.layout, LogLayout::Segmented { .. })
+    }
+
+    /// Whether the log was opened with `open_read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.writer.is_none()
+    }
+
+    /// Appends a log entry to the operation log, returning the sequence number it was given
+    pub fn append_log(&self, entry: LogEntry) -> std::io::Result<u64> {
+        self.append_log_with(entry, &|_| {})
+    }
+
+    /// Appends a log entry, running `prepare` on it once it has its sequence number
+    pub fn append_log_with(&self, mut entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        let mut writer = self.lock_writer()?;
+        entry.seq = writer.next_seq;
+        prepare(&mut entry);
+        let serialized = encode_entry(&entry)?;
+        let record_len = serialized.len() as u64 + 1;
+
+        if let LogLayout::Segmented { segment_size } = self.layout {
+            if writer.size > 0 && writer.size + record_len > segment_size {
+                let sealed = writer.segment;
+                let index = writer.index.take();
+                self.reopen_writer(&mut writer, sealed + 1)?;
+                writer.index = index.map(|mut index| {
+                    index.sealed.insert(sealed, entry.seq - 1);
+                    index.active.clear();
+                    index.unindexed = 0;
+                    index
+                });
+                info!("Rolled over to log segment {}", sealed + 1);
+
+                if self.compress_sealed {
+                    self.compress_in_background(sealed);
                 }
             }
         }
 
+        writeln!(writer.file, "{}", serialized)?;
+        writer.file.flush()?;
+        let offset = writer.size;
+        if let Some(index) = &mut writer.index {
+            index.record(entry.seq, offset);
+        }
+        writer.size += record_len;
+        writer.next_seq += 1;
+        Ok(entry.seq)
+    }
+
+    /// Reads the entries with a sequence number above `seq`, in order.
+    ///
+    /// Sealed segments holding only older entries are skipped and the file being
+    /// appended to is read from the nearest indexed offset, so a caller keeping
+    /// up with the log reads little more than what is new.
+    pub fn read_changes_since(&self, seq: u64) -> std::io::Result<Vec<LogEntry>> {
+        let Some(writer) = &self.writer else {
+            let mut entries = self.read_logs()?;
+            entries.retain(|entry| entry.seq > seq);
+            return Ok(entries);
+        };
+
+        // Note where the log ends, then read up to there while appends carry on
+        let (_maintenance, sealed, active, start, end) = {
+            let mut writer = writer.lock().expect("Failed to lock log file");
+            let maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+            writer.file.flush()?;
+            if writer.index.is_none() {
+                writer.index = Some(self.build_index(&writer)?);
+            }
+            let index = writer.index.as_ref().expect("index was just built");
+            let sealed: Vec<u64> = index
+                .sealed
+                .iter()
+                .filter(|(_, last)| **last > seq)
+                .map(|(segment, _)| *segment)
+                .collect();
+            let start = match index.active.partition_point(|(indexed, _)| *indexed <= seq) {
+                0 => 0,
+                after => index.active[after - 1].1,
+            };
+            (maintenance, sealed, writer.segment, start, writer.size)
+        };
+
+        let mut records = Vec::new();
+        if !sealed.is_empty() {
+            for (segment, path) in segment_files(Path::new(&self.log_path))? {
+                if sealed.contains(&segment) {
+                    read_file(&path, &mut records)?;
+                }
+            }
+        }
+        let mut entries: Vec<LogEntry> = records
+            .into_iter()
+            .filter_map(|record| match record {
+                LogRecord::Entry { entry, .. } => Some(entry),
+                LogRecord::Corrupt(_) => None,
+            })
+            .collect();
+
+        let mut file = File::open(writer_path(&self.log_path, self.layout, active))?;
+        file.seek(SeekFrom::Start(start))?;
+        for line in BufReader::new(file.take(end - start)).lines() {
+            let line = line?;
+            if let Ok(entry) = decode_entry(&line) {
+                entries.push(entry);
+            }
+        }
+
+        entries.retain(|entry| entry.seq > seq);
         Ok(entries)
     }
+
+    /// Scans the log for the sealed segments' last sequence numbers and the
+    /// offsets of entries in the file being appended to
+    fn build_index(&self, writer: &LogWriter) -> std::io::Result<SeqIndex> {
+        let mut index = SeqIndex::default();
+        if self.is_segmented() {
+            for (segment, path) in segment_files(Path::new(&self.log_path))? {
+                if segment < writer.segment {
+                    if let Some(last) = last_seq(&path)? {
+                        index.sealed.insert(segment, last);
+                    }
+                }
+            }
+        }
+
+        let file = File::open(writer_path(&self.log_path, self.layout, writer.segment))?;
+        let mut offset = 0;
+        for line in BufReader::new(file.take(writer.size)).lines() {
+            let line = line?;
+            let len = line.len() as u64 + 1;
+            if index.unindexed != 0 {
+                index.unindexed = (index.unindexed + 1) % INDEX_INTERVAL;
+            } else if let Ok(entry) = decode_entry(&line) {
+                index.record(entry.seq, offset);
+            }
+            offset += len;
+        }
+        Ok(index)
+    }
+
+    /// Reads all log entries from the operation log, oldest segment first
+    pub fn read_logs(&self) -> std::io::Result<Vec<LogEntry>> {
+        Ok(logged_entries(self.read_contents()?))
+    }
+
+    /// Reads all log entries along with any records that could not be decoded
+    pub fn read_contents(&self) -> std::io::Result<LogContents> {
+        Ok(split_records(self.read_records()?))
+    }
+
+    /// Reads every record in file order, keeping undecodable ones in place
+    pub fn read_records(&self) -> std::io::Result<Vec<LogRecord>> {
+        // Keeps background compression from renaming a segment between listing and reading it
+        let _maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+        self.read_records_unlocked()
+    }
+
+    /// `read_records` for callers already holding the maintenance lock
+    fn read_records_unlocked(&self) -> std::io::Result<Vec<LogRecord>> {
+        let mut records = Vec::new();
+        for path in self.segment_paths()? {
+            read_file(&path, &mut records)?;
+        }
+        Ok(records)
+    }
+
+    /// Total size in bytes of the files making up the log
+    pub fn size_bytes(&self) -> std::io::Result<u64> {
+        if let Some(writer) = &self.writer {
+            writer.lock().expect("Failed to lock log file").file.flush()?;
+        }
+
+        let _maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+        let mut size = 0;
+        for path in self.segment_paths()? {
+            size += fs::metadata(path)?.len();
+        }
+        Ok(size)
+    }
+
+    /// Lists the files making up the log, oldest first
+    pub fn segment_paths(&self) -> std::io::Result<Vec<PathBuf>> {
+        segment_paths(&self.log_path, self.layout)
+    }
+
+    /// Compacts the log down to the `SetTimer` records of timers that are still
+    /// live, headed by a `Compacted` marker.
+    ///
+    /// Liveness is decided from the log itself while appends are blocked, so
+    /// timers removed concurrently are never carried forward. For a segmented
+    /// log the live records are written to a fresh segment (a sealed, compressed
+    /// snapshot segment when compression is enabled) and every older segment is
+    /// deleted; for a single file the log is rewritten in place.
+    pub fn compact(&self) -> std::io::Result<CompactionReport> {
+        let mut writer = self.lock_writer()?;
+        let _maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+        writer.file.flush()?;
+
+        let entries = logged_entries(split_records(self.read_records_unlocked()?));
+        let live = compacted_entries(&entries);
+        let mut report = CompactionReport {
+            entries_before: entries.len(),
+            entries_after: live.len(),
+            segments_removed: 0,
+        };
+
+        let lines = live.iter().map(encode_entry).collect::<serde_json::Result<Vec<_>>>()?;
+
+        // The dropped records may include the highest sequence number handed out so far
+        write_lines(&seq_path(&self.log_path, self.layout), false, [writer.next_seq.to_string()])?;
+
+        match self.layout {
+            LogLayout::File => {
+                let tmp_path = format!("{}.compact.tmp", self.log_path);
+                write_lines(Path::new(&tmp_path), false, &lines)?;
+                fs::rename(&tmp_path, &self.log_path)?;
+                self.reopen_writer(&mut writer, 0)?;
+            }
+            LogLayout::Segmented { .. } => {
+                let dir = Path::new(&self.log_path);
+                let sealed = segment_files(dir)?;
+                let snapshot = writer.segment + 1;
+                let (snapshot_path, active) = if self.compress_sealed {
+                    (compressed_segment_path(dir, snapshot), snapshot + 1)
+                } else {
+                    (segment_path(dir, snapshot), snapshot)
+                };
+
+                let tmp_path = snapshot_path.with_extension("compact.tmp");
+                write_lines(&tmp_path, self.compress_sealed, &lines)?;
+                fs::rename(&tmp_path, &snapshot_path)?;
+                self.reopen_writer(&mut writer, active)?;
+
+                for (_, path) in sealed {
+                    fs::remove_file(path)?;
+                    report.segments_removed += 1;
+                }
+            }
+        }
+
+        info!(
+            "Compacted log {}: {} entries -> {} entries, {} segments removed",
+            self.log_path, report.entries_before, report.entries_after, report.segments_removed
+        );
+        Ok(report)
+    }
+
+    /// Copies the log to `dest` as it stood at the moment of the call.
+    ///
+    /// Appends are blocked only long enough to flush and note where the log
+    /// ends; the copy itself runs while new records keep being appended, and
+    /// stops at that point. Compaction, repair and segment compression wait
+    /// until the copy is done. `dest` becomes a file or a segment directory
+    /// matching this log, and must not already exist.
+    pub fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport> {
+        if dest.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("backup destination {} already exists", dest.display()),
+            ));
+        }
+
+        // Only a read-only log lacks a writer, and then nothing can append underneath us
+        let (_maintenance, end) = match &self.writer {
+            Some(writer) => {
+                let mut writer = writer.lock().expect("Failed to lock log file");
+                let maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+                writer.file.flush()?;
+                (maintenance, Some((writer.segment, writer.size)))
+            }
+            None => (self.maintenance.lock().expect("Failed to lock log maintenance"), None),
+        };
+
+        let mut report = BackupReport::default();
+        match self.layout {
+            LogLayout::File => {
+                report.bytes = copy_file(Path::new(&self.log_path), dest, end.map(|(_, size)| size))?;
+                report.files = 1;
+            }
+            LogLayout::Segmented { .. } => {
+                fs::create_dir_all(dest)?;
+                for (segment, path) in segment_files(Path::new(&self.log_path))? {
+                    let limit = match end {
+                        Some((active, _)) if segment > active => break,
+                        Some((active, size)) if segment == active => Some(size),
+                        _ => None,
+                    };
+                    let name = path.file_name().expect("segment files have names");
+                    report.bytes += copy_file(&path, &dest.join(name), limit)?;
+                    report.files += 1;
+                }
+            }
+        }
+        // Without it a restored log could hand out sequence numbers again after a compaction
+        let sequence = self.sequence_path();
+        if sequence.exists() {
+            report.bytes += copy_file(&sequence, &seq_path(&dest.to_string_lossy(), self.layout), None)?;
+            report.files += 1;
+        }
+
+        info!(
+            "Backed up log {} to {}: {} files, {} bytes",
+            self.log_path,
+            dest.display(),
+            report.files,
+            report.bytes
+        );
+        Ok(report)
+    }
+
+    /// Removes undecodable records from the log, moving them to a quarantine file.
+    ///
+    /// Affected files are rewritten without the bad lines (via a temporary file
+    /// and rename) and each removed record is appended to the quarantine file
+    /// along with its location and decode error. Returns the quarantined records.
+    pub fn repair(&self) -> std::io::Result<Vec<CorruptRecord>> {
+        let mut writer = self.lock_writer()?;
+        let _maintenance = self.maintenance.lock().expect("Failed to lock log maintenance");
+        writer.file.flush()?;
+
+        let mut quarantined = Vec::new();
+        for path in self.segment_paths()? {
+            let mut good_lines = Vec::new();
+            let mut bad_records = Vec::new();
+
+            for (line_no, line) in open_reader(&path)?.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match decode_entry(&line) {
+                    Ok(_) => good_lines.push(line),
+                    Err(e) => bad_records.push(CorruptRecord {
+                        location: format!("{}:{}", path.display(), line_no + 1),
+                        raw: line,
+                        error: e.to_string(),
+                    }),
+                }
+            }
+
+            if bad_records.is_empty() {
+                continue;
+            }
+
+            let mut quarantine = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.quarantine_path())?;
+            for record in &bad_records {
+                writeln!(quarantine, "{}", serde_json::to_string(record)?)?;
+            }
+            quarantine.sync_all()?;
+
+            let tmp_path = path.with_extension("repair.tmp");
+            write_lines(&tmp_path, is_compressed(&path), &good_lines)?;
+            fs::rename(&tmp_path, &path)?;
+
+            warn!("Quarantined {} corrupt records from {}", bad_records.len(), path.display());
+            quarantined.extend(bad_records);
+        }
+
+        let segment = writer.segment;
+        self.reopen_writer(&mut writer, segment)?;
+        Ok(quarantined)
+    }
+
+    /// Path of the file that receives records removed by `repair`
+    pub fn quarantine_path(&self) -> PathBuf {
+        match self.layout {
+            LogLayout::File => PathBuf::from(format!("{}.quarantine", self.log_path)),
+            LogLayout::Segmented { .. } => Path::new(&self.log_path).join("quarantine.jsonl"),
+        }
+    }
+
+    /// Path of the file recording the next sequence number across compactions
+    pub(crate) fn sequence_path(&self) -> PathBuf {
+        seq_path(&self.log_path, self.layout)
+    }
+
+    /// Locks the writer, failing if the log was opened read-only
+    fn lock_writer(&self) -> std::io::Result<MutexGuard<'_, LogWriter>> {
+        match &self.writer {
+            Some(writer) => Ok(writer.lock().expect("Failed to lock log file")),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("log {} was opened read-only", self.log_path),
+            )),
+        }
+    }
+
+    /// Compresses a just-sealed segment without blocking appends
+    fn compress_in_background(&self, segment: u64) {
+        let dir = PathBuf::from(&self.log_path);
+        let maintenance = self.maintenance.clone();
+        thread::spawn(move || {
+            let _maintenance = maintenance.lock().expect("Failed to lock log maintenance");
+            if let Err(e) = compress_segment(&dir, segment) {
+                report_compression_error(segment, e);
+            }
+        });
+    }
+
+    /// Points the writer at the given segment (or the single log file), appending to it
+    fn reopen_writer(&self, writer: &mut LogWriter, segment: u64) -> std::io::Result<()> {
+        writer.file.flush()?;
+        let next_seq = writer.next_seq;
+        *writer = LogWriter::open(&writer_path(&self.log_path, self.layout, segment), segment)?;
+        writer.next_seq = next_seq;
+        Ok(())
+    }
+}
+
+impl LogWriter {
+    /// Opens `path` for appending
+    fn open(path: &Path, segment: u64) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(LogWriter {
+            size: file.metadata()?.len(),
+            file: BufWriter::new(file),
+            segment,
+            next_seq: 1,
+            index: None,
+        })
+    }
+}
+
+/// Lists the files making up a log, oldest first
+fn segment_paths(log_path: &str, layout: LogLayout) -> std::io::Result<Vec<PathBuf>> {
+    match layout {
+        LogLayout::File => Ok(vec![PathBuf::from(log_path)]),
+        LogLayout::Segmented { .. } => {
+            let dir = Path::new(log_path);
+            Ok(segment_files(dir)?.into_iter().map(|(_, path)| path).collect())
+        }
+    }
+}
+
+/// Path of the file appended to for the given layout and segment
+fn writer_path(log_path: &str, layout: LogLayout, segment: u64) -> PathBuf {
+    match layout {
+        LogLayout::File => PathBuf::from(log_path),
+        LogLayout::Segmented { .. } => segment_path(Path::new(log_path), segment),
+    }
+}
+
+/// Path of the advisory lock file for a log
+fn lock_path(log_path: &str, layout: LogLayout) -> PathBuf {
+    match layout {
+        LogLayout::File => PathBuf::from(format!("{}.lock", log_path)),
+        LogLayout::Segmented { .. } => Path::new(log_path).join("LOCK"),
+    }
+}
+
+/// Path of the file recording the next sequence number across compactions
+fn seq_path(log_path: &str, layout: LogLayout) -> PathBuf {
+    match layout {
+        LogLayout::File => PathBuf::from(format!("{}.seq", log_path)),
+        LogLayout::Segmented { .. } => Path::new(log_path).join("SEQUENCE"),
+    }
+}
+
+/// Works out the sequence number to continue from: one past the last entry in
+/// the log, or the number recorded by the last compaction if that is higher
+fn next_seq(log_path: &str, layout: LogLayout) -> std::io::Result<u64> {
+    let mut next = 1;
+    for path in segment_paths(log_path, layout)?.iter().rev() {
+        if let Some(seq) = last_seq(path)? {
+            next = seq + 1;
+            break;
+        }
+    }
+
+    match fs::read_to_string(seq_path(log_path, layout)) {
+        Ok(recorded) => {
+            let recorded = recorded.trim().parse::<u64>().map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid sequence file: {}", e))
+            })?;
+            Ok(next.max(recorded))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(next),
+        Err(e) => Err(e),
+    }
+}
+
+/// Fails with `NotFound` unless the log file or directory exists
+fn ensure_exists(log_path: &str) -> std::io::Result<()> {
+    if !Path::new(log_path).exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("log not found: {}", log_path),
+        ));
+    }
+    Ok(())
+}
+
+/// Separates decoded entries from records that could not be decoded
+fn split_records(records: Vec<LogRecord>) -> LogContents {
+    let mut contents = LogContents::default();
+    for record in records {
+        match record {
+            LogRecord::Entry { entry, .. } => contents.entries.push(entry),
+            LogRecord::Corrupt(corrupt) => contents.corrupt.push(corrupt),
+        }
+    }
+    contents
+}
+
+/// Logs a warning for every undecodable record and returns the decoded entries
+fn logged_entries(contents: LogContents) -> Vec<LogEntry> {
+    for record in &contents.corrupt {
+        warn!("Failed to deserialize log entry at {}: {}", record.location, record.error);
+    }
+    contents.entries
+}
+
+/// Reads and decodes every entry in a single log file
+fn read_file(path: &Path, records: &mut Vec<LogRecord>) -> std::io::Result<()> {
+    let reader = open_reader(path)?;
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            let location = format!("{}:{}", path.display(), line_no + 1);
+            records.push(match decode_entry(&line) {
+                Ok(entry) => LogRecord::Entry { location, entry },
+                Err(e) => LogRecord::Corrupt(CorruptRecord {
+                    location,
+                    raw: line,
+                    error: e.to_string(),
+                }),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the latest `SetTimer` entry of every timer not removed later in the log,
+/// with any later reschedules folded in, in log order
+pub(crate) fn live_entries(entries: &[LogEntry]) -> Vec<LogEntry> {
+    let mut live: HashMap<Uuid, (usize, LogEntry)> = HashMap::new();
+    // Only the latest checkpoint matters, as the last time the process was seen running
+    let mut checkpoint = None;
+    for (index, entry) in entries.iter().enumerate() {
+        match &entry.operation {
+            LogOperation::SetTimer { timer_id, .. } => {
+                live.insert(*timer_id, (index, entry.clone()));
+            }
+            LogOperation::RemoveTimer { timer_id } | LogOperation::FireTimer { timer_id } => {
+                live.remove(timer_id);
+            }
+            LogOperation::RescheduleTimer { timer_id, .. } => {
+                if let Some((set_index, set)) = live.get_mut(timer_id) {
+                    apply_reschedule(set, entry);
+                    *set_index = index;
+                }
+            }
+            LogOperation::Checkpoint => checkpoint = Some((index, entry.clone())),
+            LogOperation::ShiftDeadlines { offset_ms } => {
+                for (_, set) in live.values_mut() {
+                    apply_shift(set, *offset_ms);
+                }
+            }
+            LogOperation::Compacted => {}
+        }
+    }
+
+    let mut live: Vec<_> = live.into_values().chain(checkpoint).collect();
+    live.sort_by_key(|(index, _)| *index);
+    live.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Returns what compaction keeps of `entries`: their live entries, headed by a
+/// `Compacted` marker timestamped with the newest entry they replace
+pub(crate) fn compacted_entries(entries: &[LogEntry]) -> Vec<LogEntry> {
+    let Some(through) = entries.iter().map(|entry| entry.timestamp).max() else {
+        return Vec::new();
+    };
+    let mut compacted = vec![LogEntry::new(through, LogOperation::Compacted)];
+    compacted.extend(live_entries(entries));
+    compacted
+}
+
+/// Folds a `RescheduleTimer` entry into the `SetTimer` entry of the same timer,
+/// which takes over the reschedule's sequence number and timestamp
+pub(crate) fn apply_reschedule(set: &mut LogEntry, reschedule: &LogEntry) {
+    if let (
+        LogOperation::SetTimer { expires_at, .. },
+        LogOperation::RescheduleTimer {
+            expires_at: new_expires_at,
+            ..
+        },
+    ) = (&mut set.operation, &reschedule.operation)
+    {
+        *expires_at = *new_expires_at;
+        set.seq = reschedule.seq;
+        set.timestamp = reschedule.timestamp;
+    }
+}
+
+/// Moves the deadline of a `SetTimer` entry by `offset_ms`, keeping its
+/// sequence number so it keeps its place among timers due at the same time
+pub(crate) fn apply_shift(set: &mut LogEntry, offset_ms: i64) {
+    if let LogOperation::SetTimer { expires_at, .. } = &mut set.operation {
+        *expires_at = expires_at.saturating_add_signed(offset_ms);
+    }
+}
+
+/// Replaces every `ShiftDeadlines` entry with a `RescheduleTimer` entry for
+/// each timer live at that point, carrying the timer's own sequence number.
+///
+/// A shift applies to the timers of the log it was written to, so logs that
+/// are replayed one after another must have their shifts expanded first.
+pub(crate) fn expand_shifts(entries: Vec<LogEntry>) -> Vec<LogEntry> {
+    let mut live: HashMap<Uuid, (u64, u64)> = HashMap::new();
+    let mut expanded = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match &entry.operation {
+            LogOperation::SetTimer { timer_id, expires_at, .. }
+            | LogOperation::RescheduleTimer { timer_id, expires_at } => {
+                live.insert(*timer_id, (*expires_at, entry.seq));
+            }
+            LogOperation::RemoveTimer { timer_id } | LogOperation::FireTimer { timer_id } => {
+                live.remove(timer_id);
+            }
+            LogOperation::Checkpoint | LogOperation::Compacted => {}
+            LogOperation::ShiftDeadlines { offset_ms } => {
+                let mut shifted: Vec<_> = live.iter_mut().collect();
+                shifted.sort_by_key(|(_, (_, seq))| *seq);
+                for (timer_id, (expires_at, seq)) in shifted {
+                    *expires_at = expires_at.saturating_add_signed(*offset_ms);
+                    let mut reschedule = LogEntry::new(
+                        entry.timestamp,
+                        LogOperation::RescheduleTimer {
+                            timer_id: *timer_id,
+                            expires_at: *expires_at,
+                        },
+                    );
+                    reschedule.seq = *seq;
+                    expanded.push(reschedule);
+                }
+                continue;
+            }
+        }
+        expanded.push(entry);
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::TimerStore;
+
+    fn temp_log_dir() -> String {
+        let dir = std::env::temp_dir().join(format!("timer_ship_segments_{}", Uuid::new_v4()));
+        format!("{}/", dir.display())
+    }
+
+    fn set_entry(timer_id: Uuid) -> LogEntry {
+        LogEntry::new(
+            1,
+            LogOperation::SetTimer {
+                timer_id,
+                expires_at: 1000,
+                data: "x".repeat(100),
+                created_at: 0,
+                duration_ms: 1000,
+                priority: 0,
+                uptime: false,
+                key: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_segments_roll_over() {
+        let dir = temp_log_dir();
+        let log = OpLog::segmented(&dir, 512).unwrap();
+        for _ in 0..20 {
+            log.append_log(set_entry(Uuid::new_v4())).unwrap();
+        }
+
+        assert!(log.segment_paths().unwrap().len() > 1);
+        assert_eq!(log.read_logs().unwrap().len(), 20);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sealed_segments_compressed() {
+        let dir = temp_log_dir();
+        let log = OpLog::segmented(&dir, 512).unwrap().with_compression(true);
+        let live_id = Uuid::new_v4();
+        log.append_log(set_entry(live_id)).unwrap();
+        for _ in 0..20 {
+            log.append_log(set_entry(Uuid::new_v4())).unwrap();
+        }
+        log.compress_sealed_segments().unwrap();
+
+        let paths = log.segment_paths().unwrap();
+        assert!(paths[..paths.len() - 1].iter().all(|path| is_compressed(path)));
+        assert_eq!(log.read_logs().unwrap().len(), 21);
+
+        log.compact().unwrap();
+        drop(log);
+        let reopened = OpLog::new(&dir).unwrap();
+        reopened.append_log(set_entry(Uuid::new_v4())).unwrap();
+        // The 21 live timers, the new one and the compaction marker
+        assert_eq!(reopened.read_logs().unwrap().len(), 23);
+        assert!(is_compressed(&reopened.segment_paths().unwrap()[0]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compact_drops_dead_segments() {
+        let dir = temp_log_dir();
+        let log = OpLog::segmented(&dir, 512).unwrap();
+        let live_id = Uuid::new_v4();
+        log.append_log(set_entry(live_id)).unwrap();
+        for _ in 0..10 {
+            let id = Uuid::new_v4();
+            log.append_log(set_entry(id)).unwrap();
+            log.append_log(LogEntry::new(2, LogOperation::RemoveTimer { timer_id: id })).unwrap();
+        }
+
+        let report = log.compact().unwrap();
+        assert_eq!(report.entries_after, 2);
+        assert_eq!(log.segment_paths().unwrap().len(), 1);
+
+        drop(log);
+        let entries = OpLog::open_read_only(&dir).unwrap().read_logs().unwrap();
+        assert_eq!(entries[0].operation, LogOperation::Compacted);
+        assert!(matches!(
+            entries[1].operation,
+            LogOperation::SetTimer { timer_id, .. } if timer_id == live_id
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_second_open_is_refused() {
+        let path = std::env::temp_dir().join(format!("timer_ship_lock_{}.log", Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let log = OpLog::new(path).unwrap();
+        log.append_log(set_entry(Uuid::new_v4())).unwrap();
+        assert_eq!(OpLog::new(path).unwrap_err().kind(), std::io::ErrorKind::ResourceBusy);
+        assert!(OpLog::open_with_timeout(path, Duration::from_millis(50)).is_err());
+        assert!(OpLog::open_read_only(path).is_err());
+        drop(log);
+
+        let reader = OpLog::open_read_only(path).unwrap();
+        let other_reader = OpLog::open_read_only(path).unwrap();
+        assert_eq!(reader.read_logs().unwrap().len(), 1);
+        assert_eq!(
+            other_reader.append_log(set_entry(Uuid::new_v4())).unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert!(OpLog::new(path).is_err());
+        drop((reader, other_reader));
+
+        assert!(OpLog::new(path).is_ok());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(format!("{}.lock", path)).unwrap();
+    }
+
+    #[test]
+    fn test_changes_since_reads_from_the_index() {
+        let dir = temp_log_dir();
+        let log = OpLog::segmented(&dir, 4096).unwrap();
+        let expected = |log: &OpLog, seq: u64| -> Vec<u64> {
+            log.read_logs().unwrap().iter().map(|entry| entry.seq).filter(|s| *s > seq).collect()
+        };
+        let changes = |log: &OpLog, seq: u64| -> Vec<u64> {
+            log.changes_since(seq).unwrap().iter().map(|entry| entry.seq).collect()
+        };
+
+        for _ in 0..150 {
+            log.append_log(set_entry(Uuid::new_v4())).unwrap();
+        }
+        assert_eq!(changes(&log, 0), expected(&log, 0));
+        for _ in 0..150 {
+            log.append_log(set_entry(Uuid::new_v4())).unwrap();
+        }
+        log.compress_sealed_segments().unwrap();
+        for seq in [0, 1, 63, 64, 65, 149, 150, 299, 300] {
+            assert_eq!(changes(&log, seq), expected(&log, seq), "changes since {}", seq);
+        }
+
+        log.compact().unwrap();
+        log.append_log(set_entry(Uuid::new_v4())).unwrap();
+        assert_eq!(changes(&log, 290), expected(&log, 290));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_seq_survives_reopen_and_compaction() {
+        let dir = temp_log_dir();
+        let log = OpLog::segmented(&dir, 512).unwrap();
+        let live_id = Uuid::new_v4();
+        assert_eq!(log.append_log(set_entry(live_id)).unwrap(), 1);
+        let removed_id = Uuid::new_v4();
+        log.append_log(set_entry(removed_id)).unwrap();
+        let last = log.append_log(LogEntry::new(2, LogOperation::RemoveTimer { timer_id: removed_id })).unwrap();
+        assert_eq!(last, 3);
+
+        log.compact().unwrap();
+        drop(log);
+        let reopened = OpLog::new(&dir).unwrap();
+        assert_eq!(reopened.append_log(set_entry(Uuid::new_v4())).unwrap(), 4);
+        drop(reopened);
+        assert_eq!(OpLog::new(&dir).unwrap().append_log(set_entry(Uuid::new_v4())).unwrap(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
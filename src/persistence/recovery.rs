@@ -1,48 +1,360 @@
-use super::{LogEntry, LogOperation, OpLog};
-use crate::core::{Timer, TimerData, Timers};
-use log::{debug, info};
+use super::{LogEntry, LogOperation, TimerStore};
+use crate::core::{Timer, TimerState};
+use crate::utils::{Clock, SystemClock};
+use log::{debug, error, info, warn};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How recovery treats records that cannot be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryPolicy {
+    /// Skip undecodable records with a warning
+    #[default]
+    Lenient,
+    /// Fail recovery if any record cannot be decoded
+    Strict,
+    /// Remove undecodable records from the store and quarantine them, then continue
+    Repair,
+}
+
+/// What happens to timers that came due while the process was down.
+///
+/// Dead-lettered timers are only reported through `TimerShipConfig::on_event`,
+/// so the policies that drop timers are refused without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedTimerPolicy {
+    /// Fire every missed timer as soon as the scheduler starts
+    #[default]
+    FireAll,
+    /// Of missed timers with the same `TimerOptions::key`, fire only the one
+    /// due last and dead-letter the rest. Timers without a key all fire.
+    FireLatestPerKey,
+    /// Fire none of them; each is removed and dead-lettered
+    Skip,
+    /// Fire them in order, spread evenly over this long from startup
+    Spread(Duration),
+}
+
+/// How far into the log recovery replays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryTarget {
+    /// Replay every entry
+    #[default]
+    Latest,
+    /// Replay entries logged at or before this time (milliseconds since UNIX
+    /// epoch). Fails if compaction has discarded history after this time.
+    Timestamp(u64),
+    /// Replay only the first `n` entries, in replay order
+    EntryIndex(usize),
+}
+
+/// Summary of a recovery run
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    /// Log entries replayed
+    pub entries_replayed: usize,
+    /// Timers live once replay finished
+    pub live_timers: usize,
+    /// Live timers whose expiration time had already passed
+    pub overdue_timers: usize,
+    /// How long the most overdue timer was overdue by
+    pub max_overdue_ms: u64,
+    /// Sum of how long each overdue timer was overdue by
+    pub total_overdue_ms: u64,
+    /// Time overdue timers are measured against: now, or the recovery target's time
+    pub as_of: u64,
+    /// Records that could not be decoded and were skipped
+    pub corrupt_records: usize,
+    /// Corrupt records moved out of the store by `RecoveryPolicy::Repair`
+    pub quarantined_records: usize,
+    /// Time between the last logged entry and recovery, during which uptime
+    /// timers were paused; only measured when recovering to the latest state
+    pub downtime_ms: u64,
+    /// Uptime timers whose deadlines were moved forward by `downtime_ms`
+    pub uptime_timers_shifted: usize,
+    /// Wall time spent reading and replaying the store
+    pub duration: Duration,
+}
+
+impl RecoveryReport {
+    /// Adds another shard's recovery to this report
+    pub(crate) fn merge(&mut self, other: RecoveryReport) {
+        self.entries_replayed += other.entries_replayed;
+        self.live_timers += other.live_timers;
+        self.overdue_timers += other.overdue_timers;
+        self.max_overdue_ms = self.max_overdue_ms.max(other.max_overdue_ms);
+        self.total_overdue_ms += other.total_overdue_ms;
+        self.as_of = self.as_of.max(other.as_of);
+        self.corrupt_records += other.corrupt_records;
+        self.quarantined_records += other.quarantined_records;
+        self.downtime_ms = self.downtime_ms.max(other.downtime_ms);
+        self.uptime_timers_shifted += other.uptime_timers_shifted;
+        self.duration += other.duration;
+    }
+}
 
 /// Manages recovery of timer state from operation logs
+#[derive(Debug)]
 pub struct RecoveryManager {
-    oplog: OpLog,
+    store: Arc<dyn TimerStore>,
+    clock: Arc<dyn Clock>,
 }
 
 impl RecoveryManager {
-    pub fn new(oplog: OpLog) -> Self {
-        Self { oplog }
+    pub fn new(store: Arc<dyn TimerStore>) -> Self {
+        Self {
+            store,
+            clock: Arc::new(SystemClock),
+        }
     }
 
-    /// Recovers timer state from operation logs
+    /// Measures overdue timers against `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Recovers timer state from operation logs, handling corrupt records according to `policy`
     pub fn recover_from_logs(
         &self,
-        timers: &Timers,
-        timer_data: &TimerData,
-    ) -> std::io::Result<()> {
-        info!("Starting recovery from logs...");
-        let logs = self.oplog.read_logs()?;
-        let log_count = logs.len();
-
-        for entry in &logs {
-            match &entry.operation {
-                LogOperation::SetTimer {
-                    timer_id,
-                    expires_at,
-                    data,
-                } => {
-                    let timer = Timer::with_id(*expires_at, *timer_id);
-                    timer_data.add_data(*timer_id, data.clone());
-                    timers.add_timer(timer);
-                    debug!("Recovered SetTimer: ID {}, expires_at {}", timer_id, expires_at);
+        state: &mut TimerState,
+        policy: RecoveryPolicy,
+    ) -> std::io::Result<RecoveryReport> {
+        self.recover_to(state, policy, RecoveryTarget::Latest)
+    }
+
+    /// Recovers timer state as it was at `target`.
+    ///
+    /// Entry indexes count entries in replay order, which after compaction no
+    /// longer matches the order the operations originally happened in.
+    pub fn recover_to(
+        &self,
+        state: &mut TimerState,
+        policy: RecoveryPolicy,
+        target: RecoveryTarget,
+    ) -> std::io::Result<RecoveryReport> {
+        info!("Starting recovery from logs (target: {:?})...", target);
+        let started = Instant::now();
+        let contents = self.store.read_all()?;
+        let mut quarantined_records = 0;
+
+        if let Some(first) = contents.corrupt.first() {
+            match policy {
+                RecoveryPolicy::Lenient => {
+                    for record in &contents.corrupt {
+                        warn!("Skipping undecodable record at {}: {}", record.location, record.error);
+                    }
+                }
+                RecoveryPolicy::Strict => {
+                    error!(
+                        "Strict recovery found {} undecodable records; refusing to start",
+                        contents.corrupt.len()
+                    );
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "{} undecodable records in timer store, first at {}: {}",
+                            contents.corrupt.len(),
+                            first.location,
+                            first.error
+                        ),
+                    ));
                 }
-                LogOperation::RemoveTimer { timer_id } => {
-                    timers.remove_timer(*timer_id);
-                    timer_data.remove_data(*timer_id);
-                    debug!("Recovered RemoveTimer: ID {}", timer_id);
+                RecoveryPolicy::Repair => {
+                    quarantined_records = self.store.repair()?.len();
+                    warn!("Repaired timer store: quarantined {} corrupt records", quarantined_records);
                 }
             }
         }
 
-        info!("Recovery completed. Processed {} log entries.", log_count);
-        Ok(())
+        let entries: Vec<_> = match target {
+            RecoveryTarget::Latest => contents.entries.iter().collect(),
+            RecoveryTarget::Timestamp(until) => {
+                let compacted_through = contents
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.operation == LogOperation::Compacted)
+                    .map(|entry| entry.timestamp)
+                    .max();
+                if let Some(through) = compacted_through.filter(|through| until < *through) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "cannot recover to {}: history up to {} was discarded by compaction",
+                            until, through
+                        ),
+                    ));
+                }
+                contents
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.timestamp <= until)
+                    .collect()
+            }
+            RecoveryTarget::EntryIndex(count) => contents.entries.iter().take(count).collect(),
+        };
+
+        for entry in &entries {
+            apply_entry(state, entry);
+        }
+
+        let as_of = match target {
+            RecoveryTarget::Latest => self.clock.now_ms(),
+            RecoveryTarget::Timestamp(until) => until,
+            RecoveryTarget::EntryIndex(_) => entries.iter().map(|entry| entry.timestamp).max().unwrap_or(0),
+        };
+        let mut downtime_ms = 0;
+        let mut uptime_timers_shifted = 0;
+        if target == RecoveryTarget::Latest {
+            // Every entry was logged while the process was running
+            let last_alive = entries.iter().map(|entry| entry.timestamp).max().unwrap_or(as_of);
+            downtime_ms = as_of.saturating_sub(last_alive);
+            uptime_timers_shifted = shift_uptime_timers(state, downtime_ms);
+        }
+
+        let live: Vec<Timer> = state.snapshot().into_iter().map(|(timer, _)| timer).collect();
+        let overdue_ms: Vec<u64> = live
+            .iter()
+            .filter(|timer| timer.is_expired(as_of))
+            .map(|timer| as_of - timer.expires_at)
+            .collect();
+        let report = RecoveryReport {
+            entries_replayed: entries.len(),
+            live_timers: live.len(),
+            overdue_timers: overdue_ms.len(),
+            max_overdue_ms: overdue_ms.iter().copied().max().unwrap_or(0),
+            total_overdue_ms: overdue_ms.iter().sum(),
+            as_of,
+            corrupt_records: contents.corrupt.len(),
+            quarantined_records,
+            downtime_ms,
+            uptime_timers_shifted,
+            duration: started.elapsed(),
+        };
+
+        info!(
+            "Recovery completed. Processed {} log entries in {:?}: {} live timers ({} overdue by up to {}ms), {} corrupt records skipped.",
+            report.entries_replayed,
+            report.duration,
+            report.live_timers,
+            report.overdue_timers,
+            report.max_overdue_ms,
+            report.corrupt_records
+        );
+        Ok(report)
+    }
+}
+
+/// Moves uptime timers' deadlines forward by `downtime_ms`, returning how many moved
+fn shift_uptime_timers(state: &mut TimerState, downtime_ms: u64) -> usize {
+    if downtime_ms == 0 {
+        return 0;
+    }
+    let uptime: Vec<Timer> = state.uptime_timers().cloned().collect();
+    for timer in &uptime {
+        state.reschedule(timer.id, timer.expires_at.saturating_add(downtime_ms), timer.seq);
+    }
+    if !uptime.is_empty() {
+        info!("Paused {} uptime timers for {}ms of downtime", uptime.len(), downtime_ms);
+    }
+    uptime.len()
+}
+
+/// Applies one logged operation to in-memory timer state
+pub(crate) fn apply_entry(state: &mut TimerState, entry: &LogEntry) {
+    match &entry.operation {
+        LogOperation::SetTimer {
+            timer_id,
+            expires_at,
+            data,
+            created_at,
+            duration_ms,
+            priority,
+            uptime,
+            key,
+            ..
+        } => {
+            // Compaction may carry a SetTimer forward, so a later record replaces an earlier one
+            let timer = Timer::with_id_at(*expires_at, *timer_id, *created_at)
+                .with_duration_ms(*duration_ms)
+                .with_priority(*priority)
+                .with_uptime(*uptime)
+                .with_key(key.clone())
+                .with_seq(entry.seq);
+            state.insert(timer, data.clone());
+            debug!("Applied SetTimer: ID {}, expires_at {}", timer_id, expires_at);
+        }
+        LogOperation::RemoveTimer { timer_id } | LogOperation::FireTimer { timer_id } => {
+            state.remove(*timer_id);
+            debug!("Applied removal: ID {}", timer_id);
+        }
+        LogOperation::RescheduleTimer { timer_id, expires_at } => {
+            state.reschedule(*timer_id, *expires_at, entry.seq);
+            debug!("Applied reschedule: ID {}, expires_at {}", timer_id, expires_at);
+        }
+        LogOperation::Checkpoint | LogOperation::Compacted => {}
+        LogOperation::ShiftDeadlines { offset_ms } => {
+            let shifted = state.shift_all(*offset_ms);
+            debug!("Applied deadline shift: {} timers moved by {}ms", shifted, offset_ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{LogEntry, OpLog};
+    use std::fs;
+
+    fn corrupt_log() -> (String, Arc<dyn TimerStore>) {
+        let path = std::env::temp_dir().join(format!("timer_ship_recovery_{}.log", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let oplog = OpLog::new(&path).unwrap();
+        let operation = LogOperation::SetTimer {
+            timer_id: uuid::Uuid::new_v4(),
+            expires_at: u64::MAX,
+            data: "ok".to_string(),
+            created_at: 0,
+            duration_ms: u64::MAX,
+            priority: 0,
+            uptime: false,
+            key: None,
+        };
+        oplog.append_log(LogEntry::new(1, operation)).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| std::io::Write::write_all(&mut f, b"{garbage\n"))
+            .unwrap();
+        (path, Arc::new(oplog))
+    }
+
+    #[test]
+    fn test_strict_policy_rejects_corrupt_log() {
+        let (path, store) = corrupt_log();
+        let result = RecoveryManager::new(store).recover_from_logs(&mut TimerState::default(), RecoveryPolicy::Strict);
+        assert!(result.is_err());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.lock", path)).unwrap();
+    }
+
+    #[test]
+    fn test_repair_policy_quarantines_corrupt_records() {
+        let (path, store) = corrupt_log();
+        let report = RecoveryManager::new(store.clone())
+            .recover_from_logs(&mut TimerState::default(), RecoveryPolicy::Repair)
+            .unwrap();
+
+        assert_eq!(report.live_timers, 1);
+        assert_eq!(report.quarantined_records, 1);
+        assert!(store.read_all().unwrap().corrupt.is_empty());
+
+        let quarantine = format!("{}.quarantine", path);
+        assert!(fs::read_to_string(&quarantine).unwrap().contains("garbage"));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&quarantine).unwrap();
+        fs::remove_file(format!("{}.lock", path)).unwrap();
     }
 }
@@ -0,0 +1,154 @@
+use super::format::decode_entry;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::{debug, warn};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Suffix of an uncompressed segment
+const SEGMENT_SUFFIX: &str = ".log";
+
+/// Suffix of a sealed segment that has been compressed
+const COMPRESSED_SUFFIX: &str = ".log.gz";
+
+/// Path of the uncompressed numbered segment inside a segment directory
+pub(crate) fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:08}{}", segment, SEGMENT_SUFFIX))
+}
+
+/// Path of the compressed numbered segment inside a segment directory
+pub(crate) fn compressed_segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:08}{}", segment, COMPRESSED_SUFFIX))
+}
+
+/// Whether a log file is gzip-compressed
+pub(crate) fn is_compressed(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(COMPRESSED_SUFFIX)
+}
+
+/// Lists the segments in `dir` in ascending order.
+///
+/// If a segment exists both compressed and uncompressed (compression was
+/// interrupted before the original was removed), the uncompressed file wins.
+pub(crate) fn segment_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+        if let Some(number) = name.strip_suffix(SEGMENT_SUFFIX).and_then(|n| n.parse::<u64>().ok()) {
+            segments.insert(number, path);
+        } else if let Some(number) = name.strip_suffix(COMPRESSED_SUFFIX).and_then(|n| n.parse::<u64>().ok()) {
+            segments.entry(number).or_insert(path);
+        }
+    }
+    Ok(segments.into_iter().collect())
+}
+
+/// Opens a log file for reading, decompressing it transparently if needed
+pub(crate) fn open_reader(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if is_compressed(path) {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Size of the tail first read when looking for the last record of a file
+const TAIL_WINDOW: u64 = 4096;
+
+/// Sequence number of the last decodable record in a log file, if any.
+///
+/// Uncompressed files are read backwards from the end in growing windows, so
+/// finding where a large log left off does not mean reading all of it.
+pub(crate) fn last_seq(path: &Path) -> io::Result<Option<u64>> {
+    if is_compressed(path) {
+        let mut last = None;
+        for line in open_reader(path)?.lines() {
+            if let Ok(entry) = decode_entry(&line?) {
+                last = Some(entry.seq);
+            }
+        }
+        return Ok(last);
+    }
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut window = TAIL_WINDOW;
+    loop {
+        let start = len.saturating_sub(window);
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(start))?;
+        file.read_to_end(&mut tail)?;
+
+        let tail = String::from_utf8_lossy(&tail);
+        // Unless the window reaches the start of the file its first line may be cut off
+        let skip = if start > 0 { 1 } else { 0 };
+        let lines: Vec<&str> = tail.lines().skip(skip).collect();
+        if let Some(entry) = lines.iter().rev().find_map(|line| decode_entry(line).ok()) {
+            return Ok(Some(entry.seq));
+        }
+        if start == 0 {
+            return Ok(None);
+        }
+        window *= 4;
+    }
+}
+
+/// Writes lines to a new file, compressing if requested, and syncs it to disk
+pub(crate) fn write_lines<I, L>(path: &Path, compress: bool, lines: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = L>,
+    L: AsRef<str>,
+{
+    let file = File::create(path)?;
+    if compress {
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        for line in lines {
+            writeln!(encoder, "{}", line.as_ref())?;
+        }
+        let mut writer = encoder.finish()?;
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    } else {
+        let mut writer = BufWriter::new(file);
+        for line in lines {
+            writeln!(writer, "{}", line.as_ref())?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()
+    }
+}
+
+/// Compresses a sealed segment in place.
+///
+/// The compressed copy is written under a temporary name and renamed into
+/// place before the original is deleted, so the segment is readable at every
+/// step. Does nothing if the uncompressed segment no longer exists.
+pub(crate) fn compress_segment(dir: &Path, segment: u64) -> io::Result<()> {
+    let source = segment_path(dir, segment);
+    if !source.exists() {
+        return Ok(());
+    }
+
+    let target = compressed_segment_path(dir, segment);
+    let tmp_path = target.with_extension("gz.tmp");
+    let lines = open_reader(&source)?.lines().collect::<io::Result<Vec<_>>>()?;
+    write_lines(&tmp_path, true, &lines)?;
+    fs::rename(&tmp_path, &target)?;
+    fs::remove_file(&source)?;
+
+    let before = lines.iter().map(|line| line.len() as u64 + 1).sum::<u64>();
+    let after = fs::metadata(&target)?.len();
+    debug!("Compressed segment {} from {} to {} bytes", segment, before, after);
+    Ok(())
+}
+
+/// Logs a failed background compression; the segment simply stays uncompressed
+pub(crate) fn report_compression_error(segment: u64, error: io::Error) {
+    warn!("Failed to compress log segment {}: {}", segment, error);
+}
@@ -0,0 +1,219 @@
+use super::{
+    oplog::expand_shifts, BackupReport, CompactionReport, CorruptRecord, LogContents, LogEntry, OpLog, TimerStore,
+};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+use uuid::Uuid;
+
+/// Store that partitions timers by ID across independent shard stores.
+///
+/// Each shard has its own log, lock and sequence numbers, so appends to
+/// different shards never contend. `TimerShip::sharded` runs one scheduler
+/// thread per shard. A timer's shard is fixed by its ID and the shard count,
+/// so a sharded log must always be reopened with the same number of shards;
+/// `open` records the count in a `SHARDS` file and refuses any other.
+///
+/// Sequence numbers are only ordered within a shard, so with more than one
+/// shard `changes_since`, and with it change streams and replication, is not
+/// supported.
+#[derive(Debug, Clone)]
+pub struct ShardedStore {
+    shards: Vec<Arc<dyn TimerStore>>,
+}
+
+impl ShardedStore {
+    /// Combines the given stores into shards, in order
+    pub fn new<S: TimerStore + 'static>(shards: Vec<S>) -> std::io::Result<Self> {
+        if shards.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "a sharded store needs at least one shard"));
+        }
+        Ok(ShardedStore {
+            shards: shards.into_iter().map(|shard| Arc::new(shard) as Arc<dyn TimerStore>).collect(),
+        })
+    }
+
+    /// Opens (creating if needed) `count` single-file logs named `shard-<n>.log` in `dir`.
+    ///
+    /// Fails if `dir` was created with a different number of shards.
+    pub fn open(dir: &str, count: usize) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        check_shard_count(Path::new(dir), count)?;
+        let shards = (0..count)
+            .map(|shard| OpLog::new(&shard_file(Path::new(dir), shard)))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Self::new(shards)
+    }
+
+    /// Gets the number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Gets the shard that holds `timer_id`
+    pub fn shard_for(&self, timer_id: Uuid) -> usize {
+        shard_for(timer_id, self.shards.len())
+    }
+
+    pub(crate) fn shards(&self) -> &[Arc<dyn TimerStore>] {
+        &self.shards
+    }
+}
+
+/// Gets the shard out of `count` that holds `timer_id`
+pub(crate) fn shard_for(timer_id: Uuid, count: usize) -> usize {
+    (timer_id.as_u128() % count as u128) as usize
+}
+
+/// Compares `count` with the count recorded in `dir`, recording it if there is none yet
+fn check_shard_count(dir: &Path, count: usize) -> std::io::Result<()> {
+    let manifest = dir.join("SHARDS");
+    let recorded = match fs::read_to_string(&manifest) {
+        Ok(recorded) => Some(recorded.trim().parse::<usize>().map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("invalid shard count in {}: {}", manifest.display(), e))
+        })?),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    // Directories from before the manifest are sized by their highest shard file
+    let existing = match recorded {
+        Some(recorded) => recorded,
+        None => {
+            let mut existing = 0;
+            for entry in fs::read_dir(dir)? {
+                let name = entry?.file_name();
+                let shard = name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix("shard-")?.strip_suffix(".log")?.parse::<usize>().ok());
+                if let Some(shard) = shard {
+                    existing = existing.max(shard + 1);
+                }
+            }
+            existing
+        }
+    };
+    if existing != 0 && existing != count {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} holds {} shards; it cannot be opened with {}", dir.display(), existing, count),
+        ));
+    }
+
+    if recorded.is_none() {
+        fs::write(&manifest, count.to_string())?;
+    }
+    Ok(())
+}
+
+fn shard_file(dir: &Path, shard: usize) -> String {
+    dir.join(format!("shard-{}.log", shard)).display().to_string()
+}
+
+impl TimerStore for ShardedStore {
+    /// Appends to the timer's shard; a checkpoint applies to every shard, and
+    /// the last shard's sequence number is returned
+    fn append(&self, entry: LogEntry) -> std::io::Result<u64> {
+        self.append_with(entry, &|_| {})
+    }
+
+    fn append_with(&self, entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        match entry.operation.timer_id() {
+            Some(timer_id) => self.shards[self.shard_for(timer_id)].append_with(entry, prepare),
+            None => {
+                let mut seq = 0;
+                for shard in &self.shards {
+                    seq = shard.append_with(entry.clone(), prepare)?;
+                }
+                Ok(seq)
+            }
+        }
+    }
+
+    /// Reads every shard in turn; shards hold disjoint timers, so each keeps its own replay order.
+    ///
+    /// Deadline shifts are expanded into per-timer reschedules, as each only
+    /// covers the timers of its own shard.
+    fn read_all(&self) -> std::io::Result<LogContents> {
+        let mut contents = LogContents::default();
+        for shard in &self.shards {
+            let shard_contents = shard.read_all()?;
+            contents.entries.extend(expand_shifts(shard_contents.entries));
+            contents.corrupt.extend(shard_contents.corrupt);
+        }
+        Ok(contents)
+    }
+
+    fn changes_since(&self, seq: u64) -> std::io::Result<Vec<LogEntry>> {
+        match self.shards.as_slice() {
+            [shard] => shard.changes_since(seq),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                "change streams are not supported across more than one shard",
+            )),
+        }
+    }
+
+    fn compact(&self) -> std::io::Result<CompactionReport> {
+        let mut report = CompactionReport::default();
+        for shard in &self.shards {
+            let shard_report = shard.compact()?;
+            report.entries_before += shard_report.entries_before;
+            report.entries_after += shard_report.entries_after;
+            report.segments_removed += shard_report.segments_removed;
+        }
+        Ok(report)
+    }
+
+    fn repair(&self) -> std::io::Result<Vec<CorruptRecord>> {
+        let mut corrupt = Vec::new();
+        for shard in &self.shards {
+            corrupt.extend(shard.repair()?);
+        }
+        Ok(corrupt)
+    }
+
+    /// Backs each shard up to `shard-<n>.log` inside the directory `dest`
+    fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport> {
+        if dest.exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("backup destination {} already exists", dest.display()),
+            ));
+        }
+
+        fs::create_dir_all(dest)?;
+        let mut report = BackupReport::default();
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard_report = shard.backup_to(Path::new(&shard_file(dest, index)))?;
+            report.files += shard_report.files;
+            report.bytes += shard_report.bytes;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_refuses_a_different_shard_count() {
+        let dir = std::env::temp_dir().join(format!("timer_ship_shards_{}", Uuid::new_v4()));
+        let path = dir.to_str().unwrap();
+        drop(ShardedStore::open(path, 3).unwrap());
+        assert!(ShardedStore::open(path, 2).is_err());
+        assert!(ShardedStore::open(path, 4).is_err());
+        assert_eq!(ShardedStore::open(path, 3).unwrap().shard_count(), 3);
+
+        // A directory written before the count was recorded
+        fs::remove_file(dir.join("SHARDS")).unwrap();
+        assert!(ShardedStore::open(path, 2).is_err());
+        assert!(ShardedStore::open(path, 4).is_err());
+        assert!(ShardedStore::open(path, 3).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
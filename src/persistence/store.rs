@@ -0,0 +1,178 @@
+use super::{
+    format::encode_entry, oplog::compacted_entries, segment::write_lines, BackupReport, CompactionReport, CorruptRecord,
+    LogContents, LogEntry, OpLog,
+};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Persistent storage for timer operations.
+///
+/// A store records every operation appended to it and can hand back a sequence
+/// of entries that, replayed in order, rebuilds the live timer set.
+pub trait TimerStore: Send + Sync + std::fmt::Debug {
+    /// Durably records an operation, giving it the next sequence number, and returns that number
+    fn append(&self, entry: LogEntry) -> std::io::Result<u64>;
+
+    /// Like `append`, but runs `prepare` on the entry once it has its sequence
+    /// number and before it is written, so e.g. encryption can be bound to it.
+    /// Stores that do not override this run `prepare` before numbering the entry.
+    fn append_with(&self, mut entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        prepare(&mut entry);
+        self.append(entry)
+    }
+
+    /// Reads back the entries needed to rebuild timer state, in replay order,
+    /// along with any records that could not be decoded
+    fn read_all(&self) -> std::io::Result<LogContents>;
+
+    /// Reads the retained entries with a sequence number above `seq`, in order.
+    ///
+    /// History discarded by `compact` is not returned, so a consumer that falls
+    /// behind a compaction sees only the records that survived it.
+    fn changes_since(&self, seq: u64) -> std::io::Result<Vec<LogEntry>> {
+        let mut entries = self.read_all()?.entries;
+        entries.retain(|entry| entry.seq > seq);
+        entries.sort_by_key(|entry| entry.seq);
+        Ok(entries)
+    }
+
+    /// Discards history that is no longer needed to rebuild the live timers
+    fn compact(&self) -> std::io::Result<CompactionReport>;
+
+    /// Removes undecodable records, setting them aside for inspection, and returns them
+    fn repair(&self) -> std::io::Result<Vec<CorruptRecord>>;
+
+    /// Writes a consistent point-in-time copy of the store to `dest` while it
+    /// stays open for writes. `dest` must not already exist.
+    fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport>;
+}
+
+impl TimerStore for OpLog {
+    fn append(&self, entry: LogEntry) -> std::io::Result<u64> {
+        self.append_log(entry)
+    }
+
+    fn append_with(&self, entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        self.append_log_with(entry, prepare)
+    }
+
+    fn read_all(&self) -> std::io::Result<LogContents> {
+        self.read_contents()
+    }
+
+    fn changes_since(&self, seq: u64) -> std::io::Result<Vec<LogEntry>> {
+        self.read_changes_since(seq)
+    }
+
+    fn compact(&self) -> std::io::Result<CompactionReport> {
+        OpLog::compact(self)
+    }
+
+    fn repair(&self) -> std::io::Result<Vec<CorruptRecord>> {
+        OpLog::repair(self)
+    }
+
+    fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport> {
+        OpLog::backup_to(self, dest)
+    }
+}
+
+/// Non-persistent store that keeps entries in memory, mainly for tests.
+///
+/// Clones share the same entries, so a clone handed to a second `TimerShip`
+/// simulates a restart.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    log: Arc<Mutex<MemoryLog>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryLog {
+    entries: Vec<LogEntry>,
+    /// Sequence number of the last appended entry
+    last_seq: u64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the count of stored entries
+    pub fn len(&self) -> usize {
+        let log = self.log.lock().expect("Failed to lock mutex");
+        log.entries.len()
+    }
+
+    /// Checks if the store holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TimerStore for MemoryStore {
+    fn append(&self, entry: LogEntry) -> std::io::Result<u64> {
+        self.append_with(entry, &|_| {})
+    }
+
+    fn append_with(&self, mut entry: LogEntry, prepare: &dyn Fn(&mut LogEntry)) -> std::io::Result<u64> {
+        let mut log = self.log.lock().expect("Failed to lock mutex");
+        log.last_seq += 1;
+        entry.seq = log.last_seq;
+        prepare(&mut entry);
+        log.entries.push(entry);
+        Ok(log.last_seq)
+    }
+
+    fn read_all(&self) -> std::io::Result<LogContents> {
+        let log = self.log.lock().expect("Failed to lock mutex");
+        Ok(LogContents {
+            entries: log.entries.clone(),
+            corrupt: Vec::new(),
+        })
+    }
+
+    fn changes_since(&self, seq: u64) -> std::io::Result<Vec<LogEntry>> {
+        let log = self.log.lock().expect("Failed to lock mutex");
+        // Entries are in sequence order, so skip straight past the ones already seen
+        let start = log.entries.partition_point(|entry| entry.seq <= seq);
+        Ok(log.entries[start..].to_vec())
+    }
+
+    fn compact(&self) -> std::io::Result<CompactionReport> {
+        let mut log = self.log.lock().expect("Failed to lock mutex");
+        let live = compacted_entries(&log.entries);
+        let report = CompactionReport {
+            entries_before: log.entries.len(),
+            entries_after: live.len(),
+            segments_removed: 0,
+        };
+        log.entries = live;
+        Ok(report)
+    }
+
+    fn repair(&self) -> std::io::Result<Vec<CorruptRecord>> {
+        // Entries are kept decoded, so there is never anything to repair
+        Ok(Vec::new())
+    }
+
+    /// Writes the entries out as a single-file operation log
+    fn backup_to(&self, dest: &Path) -> std::io::Result<BackupReport> {
+        if dest.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("backup destination {} already exists", dest.display()),
+            ));
+        }
+
+        let entries = self.log.lock().expect("Failed to lock mutex").entries.clone();
+        let lines = entries.iter().map(encode_entry).collect::<serde_json::Result<Vec<_>>>()?;
+        write_lines(dest, false, &lines)?;
+        Ok(BackupReport {
+            files: 1,
+            bytes: lines.iter().map(|line| line.len() as u64 + 1).sum(),
+        })
+    }
+}
@@ -0,0 +1,477 @@
+//! Leader/follower replication of the operation log over TCP.
+//!
+//! A primary serves its log with `TimerShip::serve_replication`. A follower
+//! connects and says which record it last applied. If the primary still holds
+//! every record after that one in memory it sends just those; otherwise it
+//! sends a snapshot of its pending timers taken on the scheduler thread,
+//! followed by the records appended after it. Followers apply both to their
+//! in-memory state without firing anything.
+//!
+//! Every message is a JSON value sealed with the shared replication `KeyRing`,
+//! so followers without the key learn nothing and cannot be fed forged records.
+
+use crate::{
+    persistence::{KeyRing, LogEntry},
+    scheduler::Command,
+    TimerShip,
+};
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// How often the primary's threads check whether they should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a follower waits before reconnecting to its primary
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long the primary waits for a new follower to say where to resume
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Records kept in memory for followers to resume from
+const FEED_CAPACITY: usize = 10_000;
+
+/// Associated data of the follower's opening message
+const HELLO_AAD: &[u8] = b"timer_ship replication hello";
+
+/// Recent records of a primary, kept so reconnecting followers can resume
+/// without being sent a snapshot
+#[derive(Debug)]
+pub(crate) struct ReplicationFeed {
+    /// Identifies this run of the primary; sequence numbers are only resumed
+    /// from within the run that sent them
+    epoch: Uuid,
+    state: Mutex<FeedState>,
+    appended: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct FeedState {
+    /// Set once replication is first served; nothing is kept before that
+    serving: bool,
+    entries: VecDeque<LogEntry>,
+    /// Sequence number of the last record dropped to make room
+    evicted_through: u64,
+}
+
+impl ReplicationFeed {
+    pub(crate) fn new() -> Self {
+        ReplicationFeed {
+            epoch: Uuid::new_v4(),
+            state: Mutex::new(FeedState::default()),
+            appended: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FeedState> {
+        self.state.lock().expect("Failed to lock replication feed")
+    }
+
+    /// Starts keeping records for followers
+    pub(crate) fn start_serving(&self) {
+        self.state().serving = true;
+    }
+
+    pub(crate) fn is_serving(&self) -> bool {
+        self.state().serving
+    }
+
+    /// Keeps a record the scheduler has just appended, dropping the oldest once full
+    pub(crate) fn push(&self, entry: LogEntry) {
+        let mut state = self.state();
+        state.entries.push_back(entry);
+        if state.entries.len() > FEED_CAPACITY {
+            if let Some(evicted) = state.entries.pop_front() {
+                state.evicted_through = evicted.seq;
+            }
+        }
+        self.appended.notify_all();
+    }
+
+    /// Sequence number of the last record kept, which a snapshot taken now reflects
+    pub(crate) fn last_seq(&self) -> u64 {
+        let state = self.state();
+        state.entries.back().map_or(state.evicted_through, |entry| entry.seq)
+    }
+
+    /// Waits up to `timeout` for records after `seq`. Returns `None` if some of
+    /// them are no longer kept, so the follower needs a snapshot instead.
+    fn entries_after(&self, seq: u64, timeout: Duration) -> Option<Vec<LogEntry>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state();
+        loop {
+            let last_seq = state.entries.back().map_or(state.evicted_through, |entry| entry.seq);
+            if seq < state.evicted_through || seq > last_seq {
+                return None;
+            }
+            let now = Instant::now();
+            if seq < last_seq || now >= deadline {
+                let start = state.entries.partition_point(|entry| entry.seq <= seq);
+                return Some(state.entries.range(start..).cloned().collect());
+            }
+            state = self
+                .appended
+                .wait_timeout(state, deadline - now)
+                .expect("Failed to lock replication feed")
+                .0;
+        }
+    }
+}
+
+/// A follower's opening message: where it left off, if anywhere
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    /// Random per connection, binding the primary's replies to this connection
+    session: Uuid,
+    /// Epoch and sequence number of the last record applied
+    resume: Option<(Uuid, u64)>,
+}
+
+/// A message from the primary
+#[derive(Debug, Serialize, Deserialize)]
+enum Frame {
+    /// Every pending timer as a `SetTimer` record, as of record `seq`
+    Snapshot { epoch: Uuid, seq: u64, timers: Vec<LogEntry> },
+    Entry(LogEntry),
+}
+
+/// Associated data of the primary's `counter`th message on a connection
+fn frame_aad(session: Uuid, counter: u64) -> Vec<u8> {
+    let mut aad = session.as_bytes().to_vec();
+    aad.extend_from_slice(&counter.to_be_bytes());
+    aad
+}
+
+/// Writes `message` as one sealed line
+fn write_sealed<T: Serialize>(writer: &mut impl Write, keys: &KeyRing, aad: &[u8], message: &T) -> io::Result<()> {
+    let plaintext = serde_json::to_vec(message)?;
+    writeln!(writer, "{}", keys.seal(aad, &plaintext))
+}
+
+/// Reads one sealed line, returning `None` once the connection is closed
+fn read_sealed<T: DeserializeOwned>(reader: &mut impl BufRead, keys: &KeyRing, aad: &[u8]) -> io::Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let plaintext = keys.open(aad, line.trim_end()).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("replication message failed authentication: {}", e),
+        )
+    })?;
+    Ok(Some(serde_json::from_slice(&plaintext)?))
+}
+
+/// Streams a primary's log to followers, created by `TimerShip::serve_replication`.
+///
+/// Stops accepting followers and disconnects the connected ones when stopped
+/// or dropped.
+#[derive(Debug)]
+pub struct ReplicationServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl ReplicationServer {
+    pub(crate) fn start<A: ToSocketAddrs>(
+        timer_ship: TimerShip,
+        feed: Arc<ReplicationFeed>,
+        keys: KeyRing,
+        addr: A,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let acceptor = {
+            let stop = stop.clone();
+            thread::spawn(move || accept_followers(listener, timer_ship, feed, keys, stop))
+        };
+        info!("Serving replication on {}", local_addr);
+
+        Ok(ReplicationServer {
+            local_addr,
+            stop,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Address followers connect to, useful when bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Disconnects every follower and stops listening
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(acceptor) = self.acceptor.take() {
+            if acceptor.join().is_err() {
+                warn!("Replication server thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ReplicationServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn accept_followers(
+    listener: TcpListener,
+    timer_ship: TimerShip,
+    feed: Arc<ReplicationFeed>,
+    keys: KeyRing,
+    stop: Arc<AtomicBool>,
+) {
+    let mut senders = Vec::new();
+    while !stop.load(Ordering::Acquire) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                info!("Follower connected from {}", peer);
+                let timer_ship = timer_ship.clone();
+                let feed = feed.clone();
+                let keys = keys.clone();
+                let stop = stop.clone();
+                senders.push(thread::spawn(move || {
+                    if let Err(e) = send_log(stream, &timer_ship, &feed, &keys, &stop) {
+                        debug!("Stopped replicating to {}: {}", peer, e);
+                    }
+                    info!("Follower {} disconnected", peer);
+                }));
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => warn!("Failed to accept follower: {}", e),
+        }
+        senders.retain(|sender: &JoinHandle<()>| !sender.is_finished());
+    }
+
+    for sender in senders {
+        let _ = sender.join();
+    }
+}
+
+/// Brings a follower up to date from where it left off, then sends each new record
+fn send_log(
+    stream: TcpStream,
+    timer_ship: &TimerShip,
+    feed: &ReplicationFeed,
+    keys: &KeyRing,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let hello: Hello = read_sealed(&mut BufReader::new(&stream), keys, HELLO_AAD)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "follower closed the connection"))?;
+    let mut cursor = hello
+        .resume
+        .and_then(|(epoch, seq)| (epoch == feed.epoch).then_some(seq));
+    let mut writer = BufWriter::new(stream);
+    let mut counter = 0;
+
+    while !stop.load(Ordering::Acquire) && !timer_ship.is_shut_down() {
+        let entries = cursor.and_then(|seq| feed.entries_after(seq, POLL_INTERVAL));
+        let frames = match entries {
+            Some(entries) => entries.into_iter().map(Frame::Entry).collect(),
+            None => {
+                let (seq, timers) = timer_ship.replica_snapshot()?;
+                debug!("Sending a snapshot of {} timers as of record {}", timers.len(), seq);
+                vec![Frame::Snapshot {
+                    epoch: feed.epoch,
+                    seq,
+                    timers,
+                }]
+            }
+        };
+        for frame in &frames {
+            cursor = Some(match frame {
+                Frame::Snapshot { seq, .. } => *seq,
+                Frame::Entry(entry) => entry.seq,
+            });
+            write_sealed(&mut writer, keys, &frame_aad(hello.session, counter), frame)?;
+            counter += 1;
+        }
+        writer.flush()?;
+    }
+    writer.get_ref().shutdown(Shutdown::Both)
+}
+
+/// Follower side: keeps the scheduler's state in step with a primary until stopped
+#[derive(Debug)]
+pub(crate) struct Replicator {
+    active: Arc<AtomicBool>,
+    connection: Arc<Mutex<Option<TcpStream>>>,
+    handle: JoinHandle<()>,
+}
+
+impl Replicator {
+    pub(crate) fn start(primary: String, keys: KeyRing, commands: Sender<Command>) -> Self {
+        let active = Arc::new(AtomicBool::new(true));
+        let connection = Arc::new(Mutex::new(None));
+        let handle = {
+            let active = active.clone();
+            let connection = connection.clone();
+            thread::spawn(move || follow(&primary, &keys, &commands, &active, &connection))
+        };
+        Replicator {
+            active,
+            connection,
+            handle,
+        }
+    }
+
+    /// Disconnects from the primary and waits for the replication thread to exit
+    pub(crate) fn stop(self) {
+        self.active.store(false, Ordering::Release);
+        if let Some(stream) = self.connection.lock().expect("Failed to lock connection").take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        self.handle.thread().unpark();
+        if self.handle.join().is_err() {
+            warn!("Replication thread panicked");
+        }
+    }
+}
+
+fn follow(
+    primary: &str,
+    keys: &KeyRing,
+    commands: &Sender<Command>,
+    active: &AtomicBool,
+    connection: &Mutex<Option<TcpStream>>,
+) {
+    // Epoch and sequence number of the last record applied, kept across reconnects
+    let mut position = None;
+    while active.load(Ordering::Acquire) {
+        match TcpStream::connect(primary) {
+            Ok(stream) => match stream.try_clone() {
+                Ok(clone) => {
+                    {
+                        // Checked under the lock so `stop` either sees this stream or stops us here
+                        let mut connection = connection.lock().expect("Failed to lock connection");
+                        if !active.load(Ordering::Acquire) {
+                            break;
+                        }
+                        *connection = Some(clone);
+                    }
+                    info!("Replicating from primary {}", primary);
+                    if let Err(e) = apply_log(stream, keys, commands, active, &mut position) {
+                        warn!("Lost connection to primary {}: {}", primary, e);
+                    }
+                }
+                Err(e) => warn!("Failed to set up connection to primary {}: {}", primary, e),
+            },
+            Err(e) => warn!("Failed to connect to primary {}: {}", primary, e),
+        }
+        thread::park_timeout(RECONNECT_INTERVAL);
+    }
+    info!("Stopped replicating from {}", primary);
+}
+
+/// Asks the primary for what came after `position` and applies it as it arrives
+fn apply_log(
+    stream: TcpStream,
+    keys: &KeyRing,
+    commands: &Sender<Command>,
+    active: &AtomicBool,
+    position: &mut Option<(Uuid, u64)>,
+) -> io::Result<()> {
+    let session = Uuid::new_v4();
+    let hello = Hello {
+        session,
+        resume: *position,
+    };
+    write_sealed(&mut &stream, keys, HELLO_AAD, &hello)?;
+
+    let mut reader = BufReader::new(stream);
+    for counter in 0.. {
+        let Some(frame) = read_sealed(&mut reader, keys, &frame_aad(session, counter))? else {
+            break;
+        };
+        if !active.load(Ordering::Acquire) {
+            break;
+        }
+        match frame {
+            Frame::Snapshot { epoch, seq, timers } => {
+                // Replaces the replicated state in one step, so it is never seen half-loaded
+                send(commands, Command::LoadReplica(timers))?;
+                *position = Some((epoch, seq));
+            }
+            Frame::Entry(entry) => {
+                let seq = entry.seq;
+                send(commands, Command::Replicate(entry))?;
+                if let Some((_, last_seq)) = position {
+                    *last_seq = seq;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn send(commands: &Sender<Command>, command: Command) -> io::Result<()> {
+    commands
+        .send(command)
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "timer scheduler has stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::LogOperation;
+
+    fn fire(seq: u64) -> LogEntry {
+        let mut entry = LogEntry::new(0, LogOperation::FireTimer { timer_id: Uuid::new_v4() });
+        entry.seq = seq;
+        entry
+    }
+
+    #[test]
+    fn test_feed_resumes_until_records_are_evicted() {
+        let feed = ReplicationFeed::new();
+        for seq in 1..=3 {
+            feed.push(fire(seq));
+        }
+        let seqs = |entries: Vec<LogEntry>| entries.iter().map(|entry| entry.seq).collect::<Vec<_>>();
+        assert_eq!(feed.entries_after(1, Duration::ZERO).map(seqs), Some(vec![2, 3]));
+        assert_eq!(feed.entries_after(3, Duration::ZERO).map(seqs), Some(vec![]));
+        assert!(feed.entries_after(4, Duration::ZERO).is_none());
+
+        for seq in 4..=FEED_CAPACITY as u64 + 2 {
+            feed.push(fire(seq));
+        }
+        assert!(feed.entries_after(1, Duration::ZERO).is_none());
+        assert_eq!(feed.entries_after(2, Duration::ZERO).map(|entries| entries.len()), Some(FEED_CAPACITY));
+    }
+
+    #[test]
+    fn test_sealed_messages_need_the_key_and_position() {
+        let keys = KeyRing::new(1, [3; 32]);
+        let session = Uuid::new_v4();
+        let mut line = Vec::new();
+        write_sealed(&mut line, &keys, &frame_aad(session, 0), &Frame::Entry(fire(7))).unwrap();
+        assert!(!String::from_utf8_lossy(&line).contains("FireTimer"));
+
+        let read = |keys: &KeyRing, counter| read_sealed::<Frame>(&mut line.as_slice(), keys, &frame_aad(session, counter));
+        assert!(matches!(read(&keys, 0), Ok(Some(Frame::Entry(entry))) if entry.seq == 7));
+        assert!(read(&keys, 1).is_err());
+        assert!(read(&KeyRing::new(1, [4; 32]), 0).is_err());
+    }
+}
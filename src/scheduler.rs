@@ -0,0 +1,612 @@
+//! Single-writer scheduler: one thread owns the pending timers, logs every
+//! change in the order it applies them, and fires expired timers.
+//!
+//! Callers talk to it through a command queue and wait on a `Completion`.
+
+use crate::{
+    change_stream::ChangeNotifier,
+    core::{Timer, TimerState},
+    events::TimerEvent,
+    persistence::{
+        oplog::live_entries, recovery::apply_entry, LogEntry, LogOperation, MissedTimerPolicy, TimerStore,
+    },
+    replication::ReplicationFeed,
+    transfer::ExportedTimer,
+    utils::{Clock, ClockJumpPolicy},
+};
+use log::{debug, error, info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc,
+    },
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// Wall-clock moves at least this much out of step with monotonic time count as a jump
+const CLOCK_JUMP_THRESHOLD_MS: i64 = 1_000;
+
+/// Longest the scheduler sleeps between clock checks while timers are pending
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the scheduler sends its answer to a command
+pub(crate) type Reply<T> = Sender<io::Result<T>>;
+
+/// Handle to an operation submitted to a `TimerShip`.
+///
+/// The result arrives once the operation has been logged and applied, so
+/// waiting on it gives the same guarantees as the blocking methods.
+#[derive(Debug)]
+pub struct Completion<T> {
+    result: Receiver<io::Result<T>>,
+}
+
+impl<T> Completion<T> {
+    pub(crate) fn channel() -> (Reply<T>, Self) {
+        let (reply, result) = mpsc::channel();
+        (reply, Completion { result })
+    }
+
+    /// A completion that has already failed
+    pub(crate) fn failed(err: io::Error) -> Self {
+        let (reply, completion) = Self::channel();
+        let _ = reply.send(Err(err));
+        completion
+    }
+
+    /// Blocks until the operation has been logged and applied
+    pub fn wait(self) -> io::Result<T> {
+        self.result.recv().unwrap_or_else(|_| Err(scheduler_stopped()))
+    }
+
+    /// Returns the result if the operation has finished, or the handle to wait on later
+    pub fn try_wait(self) -> Result<io::Result<T>, Self> {
+        match self.result.try_recv() {
+            Ok(result) => Ok(result),
+            Err(TryRecvError::Empty) => Err(self),
+            Err(TryRecvError::Disconnected) => Ok(Err(scheduler_stopped())),
+        }
+    }
+}
+
+fn scheduler_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "timer scheduler has stopped")
+}
+
+/// Requests handled by the scheduler thread, in the order they are sent
+pub(crate) enum Command {
+    Set { timer: Timer, data: String, reply: Reply<Uuid> },
+    Remove { timer_id: Uuid, reply: Reply<Option<String>> },
+    Reschedule { timer_id: Uuid, expires_at: u64, reply: Reply<bool> },
+    Import { timers: Vec<ExportedTimer>, reply: Reply<usize> },
+    /// An entry streamed from a primary, applied without logging
+    Replicate(LogEntry),
+    /// Replaces the replicated state with a primary's snapshot of `SetTimer` records
+    LoadReplica(Vec<LogEntry>),
+    /// Pending timers as `SetTimer` records, and the sequence number they reflect
+    ReplicaSnapshot { reply: Reply<(u64, Vec<LogEntry>)> },
+    /// Writes replicated state into the store and starts firing
+    Promote { reply: Reply<()> },
+    /// Stops firing for good; callbacks already queued still run
+    StopFiring { reply: Reply<()> },
+    /// The clock was moved, so the wait for the next timer starts over
+    Wake,
+    Snapshot { reply: Reply<Vec<(Timer, String)>> },
+    NextTimer { reply: Reply<Option<Timer>> },
+    Count { reply: Reply<usize> },
+}
+
+/// Work for the callback thread, in the order it happened
+pub(crate) enum Dispatch {
+    /// A fired timer, its data, and whether it was missed while down
+    Fired(Uuid, String, bool),
+    Event(TimerEvent),
+}
+
+/// Owner of a `TimerShip`'s pending timers, run on its own thread
+pub(crate) struct Scheduler {
+    state: TimerState,
+    store: Arc<dyn TimerStore>,
+    notifier: Arc<ChangeNotifier>,
+    clock: Arc<dyn Clock>,
+    /// Hands fired timers and events to the callback thread
+    dispatch: Option<Sender<Dispatch>>,
+    firing: bool,
+    stopped: bool,
+    jump_policy: ClockJumpPolicy,
+    /// Wall and monotonic time when the clock was last checked
+    last_reading: (u64, u64),
+    checkpoint_interval_ms: u64,
+    /// Monotonic time of the last checkpoint
+    last_checkpoint_ms: u64,
+    /// Recovery moved uptime deadlines that are not yet logged
+    uptime_shifted: bool,
+    missed_policy: MissedTimerPolicy,
+    /// Timers that came due while the process was down and have not fired yet
+    missed: HashSet<Uuid>,
+    /// Recent records for replication followers
+    feed: Option<Arc<ReplicationFeed>>,
+}
+
+impl Scheduler {
+    pub(crate) fn new(
+        state: TimerState,
+        store: Arc<dyn TimerStore>,
+        notifier: Arc<ChangeNotifier>,
+        clock: Arc<dyn Clock>,
+        dispatch: Option<Sender<Dispatch>>,
+        firing: bool,
+    ) -> Self {
+        let last_reading = (clock.now_ms(), clock.monotonic_ms());
+        Scheduler {
+            state,
+            store,
+            notifier,
+            clock,
+            dispatch,
+            firing,
+            stopped: false,
+            jump_policy: ClockJumpPolicy::default(),
+            last_reading,
+            checkpoint_interval_ms: 60_000,
+            last_checkpoint_ms: last_reading.1,
+            uptime_shifted: false,
+            missed_policy: MissedTimerPolicy::default(),
+            missed: HashSet::new(),
+            feed: None,
+        }
+    }
+
+    /// Keeps appended records in `feed` once replication is served
+    pub(crate) fn with_replication_feed(mut self, feed: Arc<ReplicationFeed>) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Sets what happens to timers found overdue when firing starts
+    pub(crate) fn with_missed_timer_policy(mut self, policy: MissedTimerPolicy) -> Self {
+        self.missed_policy = policy;
+        self
+    }
+
+    /// Sets how often to checkpoint while uptime timers are pending, and
+    /// whether recovery moved uptime deadlines that need logging on start
+    pub(crate) fn with_uptime_checkpoints(mut self, interval: Duration, uptime_shifted: bool) -> Self {
+        self.checkpoint_interval_ms = interval.as_millis() as u64;
+        self.uptime_shifted = uptime_shifted;
+        self
+    }
+
+    /// Sets how pending timers react when the wall clock jumps
+    pub(crate) fn with_clock_jump_policy(mut self, policy: ClockJumpPolicy) -> Self {
+        self.jump_policy = policy;
+        self
+    }
+
+    /// Handles commands and fires timers until every sender is dropped
+    pub(crate) fn run(mut self, commands: Receiver<Command>) {
+        info!("Timer processing thread started.");
+        if self.firing {
+            if self.uptime_shifted {
+                self.log_uptime_deadlines();
+            }
+            self.handle_missed();
+        }
+        loop {
+            let command = match self.time_to_next_expiry() {
+                Some(wait) => match commands.recv_timeout(wait) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                },
+            };
+            if self.firing {
+                self.check_clock();
+            }
+            if let Some(command) = command {
+                self.handle(command);
+            }
+            if self.firing {
+                self.fire_expired();
+                self.checkpoint_if_due();
+            }
+        }
+        if self.firing {
+            self.checkpoint();
+        }
+        info!("Timer processing thread stopped.");
+    }
+
+    fn time_to_next_expiry(&mut self) -> Option<Duration> {
+        if !self.firing {
+            return None;
+        }
+        let timer = self.state.next_timer()?;
+        debug!("Waiting for timer to expire: {:?}", timer);
+        let wait = Duration::from_millis(timer.get_time_left(self.clock.now_ms()));
+        Some(wait.min(CLOCK_CHECK_INTERVAL))
+    }
+
+    /// Compares how far wall time and monotonic time have moved since the last
+    /// check, and applies the jump policy if they disagree
+    fn check_clock(&mut self) {
+        let reading = (self.clock.now_ms(), self.clock.monotonic_ms());
+        let (last_wall, last_monotonic) = std::mem::replace(&mut self.last_reading, reading);
+        let wall_elapsed = reading.0 as i64 - last_wall as i64;
+        let monotonic_elapsed = reading.1 as i64 - last_monotonic as i64;
+        let jump_ms = wall_elapsed - monotonic_elapsed;
+        if jump_ms.abs() < CLOCK_JUMP_THRESHOLD_MS {
+            return;
+        }
+
+        warn!("Wall clock jumped by {}ms", jump_ms);
+        let timers_shifted = match self.jump_policy {
+            ClockJumpPolicy::KeepDeadlines => 0,
+            ClockJumpPolicy::PreserveDurations => self.shift_deadlines(jump_ms),
+        };
+        self.send(Dispatch::Event(TimerEvent::ClockJump {
+            jump_ms,
+            policy: self.jump_policy,
+            timers_shifted,
+        }));
+    }
+
+    /// Moves every pending deadline by `jump_ms`, logged as a single shift record
+    fn shift_deadlines(&mut self, jump_ms: i64) -> usize {
+        if self.state.is_empty() {
+            return 0;
+        }
+        let log_entry = LogEntry::new(self.clock.now_ms(), LogOperation::ShiftDeadlines { offset_ms: jump_ms });
+        match self.log(log_entry) {
+            Ok(_) => self.state.shift_all(jump_ms),
+            Err(e) => {
+                error!("Failed to shift deadlines after clock jump: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Logs the deadlines recovery gave uptime timers, so a later recovery
+    /// only adds the downtime since
+    fn log_uptime_deadlines(&mut self) {
+        let mut uptime: Vec<Timer> = self.state.uptime_timers().cloned().collect();
+        uptime.sort_by_key(Timer::fire_order);
+        for timer in uptime {
+            if let Err(e) = self.reschedule(timer.id, timer.expires_at) {
+                error!("Failed to log recovered deadline of uptime timer {}: {}", timer.id, e);
+            }
+        }
+        self.uptime_shifted = false;
+    }
+
+    /// Applies the missed-timer policy to timers already due as firing starts
+    fn handle_missed(&mut self) {
+        let now = self.clock.now_ms();
+        let mut overdue: Vec<(Timer, String)> = self
+            .state
+            .snapshot()
+            .into_iter()
+            .filter(|(timer, _)| timer.is_expired(now))
+            .collect();
+        if overdue.is_empty() {
+            return;
+        }
+        overdue.sort_by_key(|(timer, _)| timer.fire_order());
+        info!("{} timers were missed while down; applying {:?}", overdue.len(), self.missed_policy);
+
+        let dropped: Vec<usize> = match self.missed_policy {
+            MissedTimerPolicy::FireAll => Vec::new(),
+            MissedTimerPolicy::FireLatestPerKey => {
+                // Overdue timers are in firing order, so the last one seen per key is due last
+                let mut latest = HashMap::new();
+                for (index, (timer, _)) in overdue.iter().enumerate() {
+                    if let Some(key) = &timer.key {
+                        latest.insert(key.as_str(), index);
+                    }
+                }
+                let keep: HashSet<usize> = latest.into_values().collect();
+                (0..overdue.len())
+                    .filter(|index| overdue[*index].0.key.is_some() && !keep.contains(index))
+                    .collect()
+            }
+            MissedTimerPolicy::Skip => (0..overdue.len()).collect(),
+            MissedTimerPolicy::Spread(window) => {
+                let window_ms = window.as_millis() as u64;
+                let count = overdue.len() as u64;
+                for (index, (timer, _)) in overdue.iter().enumerate() {
+                    let expires_at = now + window_ms * index as u64 / count;
+                    if let Err(e) = self.reschedule(timer.id, expires_at) {
+                        error!("Failed to spread missed timer {}: {}", timer.id, e);
+                    }
+                }
+                Vec::new()
+            }
+        };
+
+        for index in &dropped {
+            let (timer, data) = &overdue[*index];
+            match self.remove(timer.id) {
+                Ok(_) => self.send(Dispatch::Event(TimerEvent::DeadLettered {
+                    timer_id: timer.id,
+                    data: data.clone(),
+                    overdue_ms: now - timer.expires_at,
+                })),
+                Err(e) => error!("Failed to dead-letter missed timer {}: {}", timer.id, e),
+            }
+        }
+        let dropped: HashSet<usize> = dropped.into_iter().collect();
+        self.missed = overdue
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !dropped.contains(index))
+            .map(|(_, (timer, _))| timer.id)
+            .collect();
+    }
+
+    fn checkpoint_if_due(&mut self) {
+        let elapsed = self.clock.monotonic_ms().saturating_sub(self.last_checkpoint_ms);
+        if elapsed >= self.checkpoint_interval_ms {
+            self.checkpoint();
+        }
+    }
+
+    /// Records that the process is running, if there are uptime timers to pause when it stops
+    fn checkpoint(&mut self) {
+        self.last_checkpoint_ms = self.clock.monotonic_ms();
+        if self.state.uptime_timers().next().is_none() {
+            return;
+        }
+        if let Err(e) = self.log(LogEntry::new(self.clock.now_ms(), LogOperation::Checkpoint)) {
+            error!("Failed to log checkpoint: {}", e);
+        }
+    }
+
+    fn send(&self, dispatch: Dispatch) {
+        if let Some(sender) = &self.dispatch {
+            let _ = sender.send(dispatch);
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Set { timer, data, reply } => {
+                let _ = reply.send(self.set(timer, data));
+            }
+            Command::Remove { timer_id, reply } => {
+                self.missed.remove(&timer_id);
+                let _ = reply.send(self.remove(timer_id));
+            }
+            Command::Reschedule {
+                timer_id,
+                expires_at,
+                reply,
+            } => {
+                self.missed.remove(&timer_id);
+                let _ = reply.send(self.reschedule(timer_id, expires_at));
+            }
+            Command::Import { timers, reply } => {
+                let _ = reply.send(self.import(timers));
+            }
+            Command::Replicate(entry) => apply_entry(&mut self.state, &entry),
+            Command::LoadReplica(entries) => {
+                self.state.clear();
+                for entry in &entries {
+                    apply_entry(&mut self.state, entry);
+                }
+            }
+            Command::ReplicaSnapshot { reply } => {
+                let _ = reply.send(self.replica_snapshot());
+            }
+            Command::Promote { reply } => {
+                let result = self.persist_replicated_state();
+                self.firing = result.is_ok() && !self.stopped;
+                if self.firing {
+                    self.handle_missed();
+                }
+                let _ = reply.send(result);
+            }
+            Command::StopFiring { reply } => {
+                if self.firing {
+                    self.checkpoint();
+                }
+                self.firing = false;
+                self.stopped = true;
+                // Lets the callback thread finish once it has run what is already queued
+                self.dispatch = None;
+                let _ = reply.send(Ok(()));
+            }
+            Command::Wake => {}
+            Command::Snapshot { reply } => {
+                let _ = reply.send(Ok(self.state.snapshot()));
+            }
+            Command::NextTimer { reply } => {
+                let _ = reply.send(Ok(self.state.next_timer()));
+            }
+            Command::Count { reply } => {
+                let _ = reply.send(Ok(self.state.len()));
+            }
+        }
+    }
+
+    /// Appends an entry to the store and wakes change streams and replication
+    fn log(&self, entry: LogEntry) -> io::Result<u64> {
+        let feed = self.feed.as_ref().filter(|feed| feed.is_serving());
+        let replicated = feed.map(|_| entry.clone());
+        let seq = self.store.append(entry)?;
+        self.notifier.notify(seq);
+        if let (Some(feed), Some(mut entry)) = (feed, replicated) {
+            entry.seq = seq;
+            feed.push(entry);
+        }
+        Ok(seq)
+    }
+
+    /// Pending timers as of the last record in the replication feed. Taken on
+    /// this thread, so no change can land between the two.
+    fn replica_snapshot(&self) -> io::Result<(u64, Vec<LogEntry>)> {
+        let Some(feed) = &self.feed else {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "replication is not served"));
+        };
+        let now = self.clock.now_ms();
+        let mut timers = self.state.snapshot();
+        timers.sort_by_key(|(timer, _)| timer.fire_order());
+        let entries = timers
+            .into_iter()
+            .map(|(timer, data)| {
+                let mut entry = LogEntry::new(now, set_operation(&timer, data));
+                entry.seq = timer.seq;
+                entry
+            })
+            .collect();
+        Ok((feed.last_seq(), entries))
+    }
+
+    fn set(&mut self, timer: Timer, data: String) -> io::Result<Uuid> {
+        let timer_id = timer.id;
+
+        // Log the operation first
+        let log_entry = LogEntry::new(timer.created_at, set_operation(&timer, data.clone()));
+        let seq = self.log(log_entry)?;
+
+        // Then apply the operation
+        self.state.insert(timer.with_seq(seq), data);
+        Ok(timer_id)
+    }
+
+    fn remove(&mut self, timer_id: Uuid) -> io::Result<Option<String>> {
+        // Log the operation first
+        let log_entry = LogEntry::new(self.clock.now_ms(), LogOperation::RemoveTimer { timer_id });
+        self.log(log_entry)?;
+
+        // Then apply the operation
+        let data = self.state.remove(timer_id);
+        if let Some(ref data_str) = data {
+            debug!("Removed timer data: {}", data_str);
+        } else {
+            warn!("No data found for timer ID: {}", timer_id);
+        }
+        Ok(data)
+    }
+
+    fn reschedule(&mut self, timer_id: Uuid, expires_at: u64) -> io::Result<bool> {
+        if !self.state.contains(timer_id) {
+            return Ok(false);
+        }
+        let log_entry = LogEntry::new(
+            self.clock.now_ms(),
+            LogOperation::RescheduleTimer { timer_id, expires_at },
+        );
+        let seq = self.log(log_entry)?;
+        Ok(self.state.reschedule(timer_id, expires_at, seq))
+    }
+
+    fn import(&mut self, timers: Vec<ExportedTimer>) -> io::Result<usize> {
+        let mut imported = 0;
+        for timer in timers {
+            if self.state.contains(timer.id) {
+                warn!("Skipping import of already active timer {}", timer.id);
+                continue;
+            }
+
+            // Log the operation first
+            let now = self.clock.now_ms();
+            let seq = self.log(timer.to_log_entry(now))?;
+
+            // Then apply the operation
+            let created_at = timer.created_at.unwrap_or(now);
+            self.state.insert(
+                Timer::with_id_at(timer.expires_at, timer.id, created_at)
+                    .with_priority(timer.priority)
+                    .with_uptime(timer.uptime)
+                    .with_key(timer.key)
+                    .with_seq(seq),
+                timer.data,
+            );
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// Logs every timer that has expired, then hands it to the callback thread
+    fn fire_expired(&mut self) {
+        let now = self.clock.now_ms();
+        while let Some((timer, data)) = self.state.pop_expired(now) {
+            let timer_id = timer.id;
+
+            // Log the expiration
+            let log_entry = LogEntry::new(now, LogOperation::FireTimer { timer_id });
+            if let Err(e) = self.log(log_entry) {
+                error!("Failed to log timer expiration: {}", e);
+            }
+
+            info!("Timer expired: ID {} : at: {}", timer_id, now);
+            let missed = self.missed.remove(&timer_id);
+            self.send(Dispatch::Fired(timer_id, data, missed));
+        }
+    }
+
+    /// Brings the store in line with the timers replicated into memory
+    fn persist_replicated_state(&mut self) -> io::Result<()> {
+        let stored: HashMap<Uuid, LogEntry> = live_entries(&self.store.read_all()?.entries)
+            .into_iter()
+            .filter_map(|entry| Some((entry.operation.timer_id()?, entry)))
+            .collect();
+
+        let now = self.clock.now_ms();
+        for timer_id in stored.keys() {
+            if !self.state.contains(*timer_id) {
+                self.log(LogEntry::new(now, LogOperation::RemoveTimer { timer_id: *timer_id }))?;
+            }
+        }
+        // Logged in firing order, so timers due together keep their order after a restart
+        let mut live = self.state.snapshot();
+        live.sort_by_key(|(timer, _)| timer.fire_order());
+        for (timer, data) in live {
+            let operation = set_operation(&timer, data);
+            let Some(stored) = stored.get(&timer.id) else {
+                self.log(LogEntry::new(now, operation))?;
+                continue;
+            };
+            if stored.operation == operation {
+                continue;
+            }
+            // Only the deadline of a live timer changes, and a second SetTimer
+            // for it would read as a duplicate
+            let mut rescheduled = stored.operation.clone();
+            if let LogOperation::SetTimer { expires_at, .. } = &mut rescheduled {
+                *expires_at = timer.expires_at;
+            }
+            let operation = if rescheduled == operation {
+                LogOperation::RescheduleTimer {
+                    timer_id: timer.id,
+                    expires_at: timer.expires_at,
+                }
+            } else {
+                operation
+            };
+            self.log(LogEntry::new(now, operation))?;
+        }
+        Ok(())
+    }
+}
+
+/// The record that sets `timer`
+fn set_operation(timer: &Timer, data: String) -> LogOperation {
+    LogOperation::SetTimer {
+        timer_id: timer.id,
+        expires_at: timer.expires_at,
+        data,
+        created_at: timer.created_at,
+        duration_ms: timer.duration_ms,
+        priority: timer.priority,
+        uptime: timer.uptime,
+        key: timer.key.clone(),
+    }
+}
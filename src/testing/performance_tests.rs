@@ -1,4 +1,4 @@
-use crate::{TimerShip, TimerCallback};
+use crate::{MemoryStore, TimerShip, TimerCallback};
 use std::{
     fs,
     sync::{Arc, Mutex},
@@ -8,9 +8,9 @@ use std::{
 use uuid::Uuid;
 
 fn setup_timer_ship() -> TimerShip {
-    // Create TimerShip with a dummy log path and no-op callback
+    // Create TimerShip with an in-memory store and no-op callback
     let callback: TimerCallback = Box::new(|_, _| {});
-    TimerShip::with_callback("dummy_log_path.log", Some(callback)).unwrap()
+    TimerShip::with_store(MemoryStore::new(), Some(callback)).unwrap()
 }
 
 fn bench_set_timer(c: &mut Criterion) {
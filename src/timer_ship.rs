@@ -1,15 +1,28 @@
 use crate::{
-    utils::{current_time_ms, parse_duration},
-    persistence::{LogEntry, LogOperation, OpLog},
-    core::{Timer, TimerData, Timers},
+    change_stream::{ChangeNotifier, ChangeStream},
+    events::{EventCallback, TimerEvent},
+    replication::{ReplicationFeed, ReplicationServer, Replicator},
+    scheduler::{Command, Completion, Dispatch, Reply, Scheduler},
+    utils::{parse_duration, Clock, ClockJumpPolicy, ClockWaker, SystemClock},
+    persistence::{
+        sharded_store::shard_for, BackupReport, CompactionReport, EncryptedStore, KeyRing, LogEntry, OpLog,
+        RecoveryManager, MissedTimerPolicy, RecoveryPolicy, RecoveryReport, ShardedStore, TimerStore,
+    },
+    core::{QueueKind, Timer, TimerState},
+    transfer::{read_timers, write_timers, ExportFormat, ExportedTimer},
 };
-use log::{debug, error, info, warn};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::{error, info};
 use std::{
+    io::{BufRead, Write},
+    net::ToSocketAddrs,
+    path::Path,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        mpsc::{self, Sender},
+        Arc, Mutex,
     },
-    thread::{self},
+    thread::{self, JoinHandle},
     time::Duration,
 };
 use uuid::Uuid;
@@ -24,63 +37,244 @@ pub struct TimerInfo {
     pub expires_at: u64,
     pub data: String,
     pub time_left_ms: u64,
+    /// When the timer was set, in milliseconds since UNIX epoch
+    pub created_at: u64,
+    /// Duration the timer was set for
+    pub duration_ms: u64,
+    /// Time since the timer was set, as of when this info was taken
+    pub elapsed_ms: u64,
+    /// Priority among timers due in the same millisecond
+    pub priority: u8,
+    /// Whether the timer counts only uptime
+    pub uptime: bool,
+    /// Key grouping the timer for `MissedTimerPolicy::FireLatestPerKey`
+    pub key: Option<String>,
 }
 
 impl TimerInfo {
+    /// Describes `timer` as of `now` (milliseconds since UNIX epoch)
+    pub fn new(timer: &Timer, data: String, now: u64) -> Self {
+        TimerInfo {
+            id: timer.id,
+            expires_at: timer.expires_at,
+            data,
+            time_left_ms: timer.get_time_left(now),
+            created_at: timer.created_at,
+            duration_ms: timer.duration_ms,
+            elapsed_ms: now.saturating_sub(timer.created_at),
+            priority: timer.priority,
+            uptime: timer.uptime,
+            key: timer.key.clone(),
+        }
+    }
+
+    /// How much of the timer's duration has elapsed, from 0.0 to 100.0
+    pub fn percent_complete(&self) -> f64 {
+        if self.duration_ms == 0 {
+            return 100.0;
+        }
+        (self.elapsed_ms as f64 / self.duration_ms as f64 * 100.0).min(100.0)
+    }
+
     /// Formats the time left in a human-readable format
     pub fn format_time_left(&self) -> String {
-        let ms = self.time_left_ms;
-        
-        if ms == 0 {
+        if self.time_left_ms == 0 {
             return "Expired".to_string();
         }
-        
-        let hours = ms / (1000 * 60 * 60);
-        let minutes = (ms % (1000 * 60 * 60)) / (1000 * 60);
-        let seconds = (ms % (1000 * 60)) / 1000;
-        let milliseconds = ms % 1000;
-        
-        if hours > 0 {
-            format!("{}h {}m {}s", hours, minutes, seconds)
-        } else if minutes > 0 {
-            format!("{}m {}s", minutes, seconds)
-        } else if seconds > 0 {
-            format!("{}s {}ms", seconds, milliseconds)
-        } else {
-            format!("{}ms", milliseconds)
-        }
+        format_duration_ms(self.time_left_ms)
     }
-    
-    /// Formats the expiration time as a relative timestamp
+
+    /// Formats the time since the timer was set in a human-readable format
+    pub fn format_elapsed(&self) -> String {
+        format_duration_ms(self.elapsed_ms)
+    }
+
+    /// Formats the expiration time as an absolute timestamp in the local time zone
     pub fn format_expires_at(&self) -> String {
-        use std::time::UNIX_EPOCH;
-        
-        let expires_at_secs = self.expires_at / 1000;
-        let _expires_at_system = UNIX_EPOCH + std::time::Duration::from_secs(expires_at_secs);
-        
-        // For simplicity, just show the timestamp
-        format!("in {}", self.format_time_left())
+        match DateTime::from_timestamp_millis(self.expires_at as i64) {
+            Some(utc) => utc.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+            None => self.expires_at.to_string(),
+        }
+    }
+
+    /// Formats the expiration time as an absolute RFC 3339 timestamp in UTC
+    pub fn format_expires_at_utc(&self) -> String {
+        match DateTime::from_timestamp_millis(self.expires_at as i64) {
+            Some(utc) => utc.to_rfc3339_opts(SecondsFormat::Millis, true),
+            None => self.expires_at.to_string(),
+        }
+    }
+}
+
+/// Formats a duration in milliseconds as e.g. `1h 2m 3s`, `4s 500ms` or `20ms`
+fn format_duration_ms(ms: u64) -> String {
+    let hours = ms / (1000 * 60 * 60);
+    let minutes = (ms % (1000 * 60 * 60)) / (1000 * 60);
+    let seconds = (ms % (1000 * 60)) / 1000;
+    let milliseconds = ms % 1000;
+
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else if seconds > 0 {
+        format!("{}s {}ms", seconds, milliseconds)
+    } else {
+        format!("{}ms", milliseconds)
+    }
+}
+
+/// Construction options for `TimerShip`
+#[derive(Clone)]
+pub struct TimerShipConfig {
+    /// How recovery treats records that cannot be decoded
+    pub recovery_policy: RecoveryPolicy,
+    /// Address of a primary to replicate from. The instance starts as a
+    /// passive follower that never fires and refuses writes until promoted.
+    /// Needs `replication_key`.
+    pub follow: Option<String>,
+    /// Key shared by a primary and its followers, sealing everything sent
+    /// between them. Required to serve or follow replication.
+    pub replication_key: Option<KeyRing>,
+    /// Data structure holding pending timers
+    pub queue: QueueKind,
+    /// Source of the current time; a `MockClock` lets tests fire timers without sleeping
+    pub clock: Arc<dyn Clock>,
+    /// What happens to pending timers when the wall clock is stepped
+    pub clock_jump_policy: ClockJumpPolicy,
+    /// Receives events such as clock jumps, and every expiration along with
+    /// whether it was missed; enough on its own to handle expirations
+    pub on_event: Option<EventCallback>,
+    /// What happens to timers that came due while the process was down.
+    /// Policies that drop timers need `on_event`, which receives them.
+    pub missed_timer_policy: MissedTimerPolicy,
+    /// How often a checkpoint is logged while uptime timers are pending. Any
+    /// downtime shorter than this may be counted against uptime timers.
+    pub uptime_checkpoint_interval: Duration,
+    /// Gzip sealed segments and compaction snapshots of a segmented log opened
+    /// by `TimerShip::open`. Stores passed in directly keep their own setting.
+    pub compress_segments: bool,
+    /// Encrypts timer payloads in the store given to `open` or `with_config`,
+    /// as if it were wrapped in an `EncryptedStore` with these keys. `sharded`
+    /// refuses it; wrap each shard's store instead.
+    pub encryption_key: Option<KeyRing>,
+    /// Start without firing timers, for tools that only change the timer set,
+    /// such as the `import` command. Timers that come due stay pending.
+    pub paused: bool,
+}
+
+impl Default for TimerShipConfig {
+    fn default() -> Self {
+        TimerShipConfig {
+            recovery_policy: RecoveryPolicy::default(),
+            follow: None,
+            replication_key: None,
+            queue: QueueKind::default(),
+            clock: Arc::new(SystemClock),
+            clock_jump_policy: ClockJumpPolicy::default(),
+            on_event: None,
+            missed_timer_policy: MissedTimerPolicy::default(),
+            uptime_checkpoint_interval: Duration::from_secs(60),
+            compress_segments: false,
+            encryption_key: None,
+            paused: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for TimerShipConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimerShipConfig")
+            .field("recovery_policy", &self.recovery_policy)
+            .field("follow", &self.follow)
+            .field("replication_key", &self.replication_key)
+            .field("queue", &self.queue)
+            .field("clock", &self.clock)
+            .field("clock_jump_policy", &self.clock_jump_policy)
+            .field("on_event", &self.on_event.is_some())
+            .field("missed_timer_policy", &self.missed_timer_policy)
+            .field("uptime_checkpoint_interval", &self.uptime_checkpoint_interval)
+            .field("compress_segments", &self.compress_segments)
+            .field("encryption_key", &self.encryption_key)
+            .field("paused", &self.paused)
+            .finish()
     }
 }
 
-/// Main timer management system with persistent operation logging
+/// Per-timer options for `TimerShip::set_timer_with_options`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimerOptions {
+    /// Among timers due in the same millisecond, higher priorities fire first.
+    /// Timers with equal deadlines and priorities fire in the order they were set.
+    /// With more than one shard, both orders hold only among timers in the same shard.
+    pub priority: u8,
+    /// Count only time while a `TimerShip` is running, so time the process is
+    /// down does not use up the timer. The deadline is pushed back by the
+    /// downtime at recovery, measured from the last logged checkpoint.
+    pub uptime: bool,
+    /// Groups timers that stand for the same thing, such as repeated reminders
+    /// for one order. `MissedTimerPolicy::FireLatestPerKey` fires only the last
+    /// missed timer of each key.
+    pub key: Option<String>,
+}
+
+/// Main timer management system with persistent operation logging.
+///
+/// Every change is handed to a scheduler thread that owns the pending timers,
+/// logs the change and then applies it, so changes reach the log in the order
+/// they are applied. Expiration callbacks run on a thread of their own.
+/// A sharded instance runs one scheduler per shard.
 #[derive(Clone)]
 pub struct TimerShip {
-    timers: Arc<Timers>,
-    timer_data: Arc<TimerData>,
-    oplog: Arc<OpLog>,
-    recovery_complete: Arc<AtomicBool>,
-    callback: Option<Arc<TimerCallback>>,
+    store: Arc<dyn TimerStore>,
+    recovery_report: Arc<RecoveryReport>,
+    clock: Arc<dyn Clock>,
+    /// One queue per shard. Declared before `workers` so the schedulers see
+    /// every sender gone before they are joined.
+    commands: Vec<Sender<Command>>,
+    shutdown: Arc<AtomicBool>,
+    notifier: Arc<ChangeNotifier>,
+    /// Recent records for followers, with the key sealing them; single-shard only
+    replication: Option<Arc<ReplicationFeed>>,
+    replication_key: Option<KeyRing>,
+    workers: Arc<Mutex<Workers>>,
+}
+
+/// Threads serving a `TimerShip`, stopped once its last clone is dropped
+#[derive(Default)]
+struct Workers {
+    schedulers: Vec<JoinHandle<()>>,
+    /// Run the expiration and event callbacks, if there are any
+    dispatchers: Vec<JoinHandle<()>>,
+    replicator: Option<Replicator>,
+    /// Wakes the schedulers when the clock is moved by hand
+    clock_waker: Option<Arc<ClockWaker>>,
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        // The waker holds senders of commands too
+        self.clock_waker = None;
+        // The replicator holds the last sender of commands, so it goes first
+        if let Some(replicator) = self.replicator.take() {
+            replicator.stop();
+        }
+        for handle in self.schedulers.drain(..).chain(self.dispatchers.drain(..)) {
+            if handle.thread().id() != thread::current().id() && handle.join().is_err() {
+                error!("Timer thread panicked");
+            }
+        }
+    }
 }
 
 impl std::fmt::Debug for TimerShip {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TimerShip")
-            .field("timers", &self.timers)
-            .field("timer_data", &self.timer_data)
-            .field("oplog", &self.oplog)
-            .field("recovery_complete", &self.recovery_complete)
-            .field("has_callback", &self.callback.is_some())
+            .field("store", &self.store)
+            .field("recovery_report", &self.recovery_report)
+            .field("shards", &self.commands.len())
+            .field("shutdown", &self.shutdown)
+            .field("is_follower", &self.is_follower())
             .finish()
     }
 }
@@ -93,222 +287,1067 @@ impl TimerShip {
 
     /// Creates a new TimerShip with operation logging and expiration callback
     pub fn with_callback(log_path: &str, callback: Option<TimerCallback>) -> std::io::Result<Self> {
-        let oplog = Arc::new(OpLog::new(log_path)?);
-        let recovery_complete = Arc::new(AtomicBool::new(false));
-
-        let ts = TimerShip {
-            timers: Arc::new(Timers::new()),
-            timer_data: Arc::new(TimerData::new()),
-            oplog,
-            recovery_complete: recovery_complete.clone(),
-            callback: callback.map(Arc::new),
-        };
+        Self::open(log_path, callback, TimerShipConfig::default())
+    }
 
-        // Recover from logs before starting the timer thread
-        ts.recover_from_logs()?;
-        recovery_complete.store(true, Ordering::Relaxed);
-        info!("Recovery from logs completed.");
+    /// Creates a new TimerShip logging to a file or segment directory with explicit options
+    pub fn open(log_path: &str, callback: Option<TimerCallback>, config: TimerShipConfig) -> std::io::Result<Self> {
+        let log = OpLog::new(log_path)?.with_compression(config.compress_segments);
+        Self::with_config(log, callback, config)
+    }
 
-        // Start the timer processing thread only after recovery
-        {
-            let timer_ship = ts.clone();
-            thread::spawn(move || {
-                // Wait for recovery to complete
-                while !timer_ship.recovery_complete.load(Ordering::Relaxed) {
-                    thread::sleep(Duration::from_millis(10));
-                }
+    /// Creates a new TimerShip persisting to the given store
+    pub fn with_store<S: TimerStore + 'static>(store: S, callback: Option<TimerCallback>) -> std::io::Result<Self> {
+        Self::with_config(store, callback, TimerShipConfig::default())
+    }
 
-                info!("Timer processing thread started.");
-                loop {
-                    let timer = timer_ship.get_expiring_timer();
-                    if let Some(timer) = timer {
-                        let now = current_time_ms();
-                        if timer.is_expired(now) {
-                            let timer_id = timer.id;
-                            // Use internal removal to avoid double logging
-                            let data = timer_ship.remove_timer_internal(timer_id);
-                            
-                            // Log the removal operation
-                            let log_entry = LogEntry {
-                                timestamp: now,
-                                operation: LogOperation::RemoveTimer { timer_id },
-                            };
-                            if let Err(e) = timer_ship.oplog.append_log(log_entry) {
-                                error!("Failed to log timer removal: {}", e);
-                            }
+    /// Creates a new TimerShip persisting to the given store with explicit options
+    pub fn with_config<S: TimerStore + 'static>(
+        store: S,
+        callback: Option<TimerCallback>,
+        config: TimerShipConfig,
+    ) -> std::io::Result<Self> {
+        let store: Arc<dyn TimerStore> = match config.encryption_key.clone() {
+            Some(keys) => Arc::new(EncryptedStore::new(store, keys)),
+            None => Arc::new(store),
+        };
+        Self::start(vec![store.clone()], store, callback, config)
+    }
 
-                            info!("Timer expired: ID {} : at: {}", timer_id, now);
+    /// Creates a new TimerShip with one scheduler thread and one store per shard.
+    ///
+    /// Timers are assigned to shards by ID and the API behaves as for a single
+    /// store, with listing and counting covering every shard, except that
+    /// change streams and replication need a single sequence of records. With
+    /// more than one shard, `changes_since`, `watch` and `serve_replication`
+    /// fail with `ErrorKind::Unsupported`, and a follower cannot be created.
+    /// Shards fire independently, so timers due together are ordered by
+    /// priority and scheduling order only within a shard.
+    pub fn sharded(
+        store: ShardedStore,
+        callback: Option<TimerCallback>,
+        config: TimerShipConfig,
+    ) -> std::io::Result<Self> {
+        if store.shard_count() > 1 && config.follow.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a follower cannot be sharded",
+            ));
+        }
+        if config.encryption_key.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "TimerShipConfig::encryption_key is not applied to shards; wrap each shard's store in EncryptedStore",
+            ));
+        }
+        let shards = store.shards().to_vec();
+        Self::start(shards, Arc::new(store), callback, config)
+    }
 
-                            // Call the expiration callback if provided
-                            if let (Some(callback), Some(data)) = (&timer_ship.callback, data) {
-                                callback(timer_id, data);
+    /// Recovers each shard, then starts its scheduler and callback threads
+    fn start(
+        shards: Vec<Arc<dyn TimerStore>>,
+        store: Arc<dyn TimerStore>,
+        callback: Option<TimerCallback>,
+        config: TimerShipConfig,
+    ) -> std::io::Result<Self> {
+        let drops_timers = matches!(
+            config.missed_timer_policy,
+            MissedTimerPolicy::FireLatestPerKey | MissedTimerPolicy::Skip
+        );
+        if drops_timers && config.on_event.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} drops missed timers, so it needs TimerShipConfig::on_event to receive them",
+                    config.missed_timer_policy
+                ),
+            ));
+        }
+
+        // Recover from logs before starting the timer threads
+        let mut recovery_report = RecoveryReport::default();
+        let mut states = Vec::with_capacity(shards.len());
+        for (shard, shard_store) in shards.iter().enumerate() {
+            let mut state = TimerState::new(config.queue, config.clock.now_ms());
+            let shard_report = RecoveryManager::new(shard_store.clone())
+                .with_clock(config.clock.clone())
+                .recover_from_logs(&mut state, config.recovery_policy)?;
+            let uptime_shifted = shard_report.uptime_timers_shifted > 0;
+            recovery_report.merge(shard_report);
+            let misplaced = state
+                .snapshot()
+                .into_iter()
+                .map(|(timer, _)| timer.id)
+                .find(|timer_id| shard_for(*timer_id, shards.len()) != shard);
+            if let Some(timer_id) = misplaced {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "timer {} was recovered from shard {} but belongs in shard {}; was the shard count changed?",
+                        timer_id,
+                        shard,
+                        shard_for(timer_id, shards.len())
+                    ),
+                ));
+            }
+            states.push((state, uptime_shifted));
+        }
+        info!("Recovery from logs completed.");
+        if config.follow.is_some() && config.replication_key.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a follower needs TimerShipConfig::replication_key",
+            ));
+        }
+
+        let replication = (shards.len() == 1).then(|| Arc::new(ReplicationFeed::new()));
+        let mut workers = Workers::default();
+        let callback = callback.map(Arc::new);
+        let notifier = Arc::new(ChangeNotifier::default());
+        let firing = config.follow.is_none() && !config.paused;
+        let mut commands = Vec::with_capacity(shards.len());
+        for ((state, uptime_shifted), shard_store) in states.into_iter().zip(shards) {
+            let dispatch = (callback.is_some() || config.on_event.is_some()).then(|| {
+                let (dispatch, work) = mpsc::channel();
+                let callback = callback.clone();
+                let on_event = config.on_event.clone();
+                workers.dispatchers.push(thread::spawn(move || {
+                    for item in work {
+                        match item {
+                            Dispatch::Fired(timer_id, data, missed) => match (&on_event, &callback) {
+                                (Some(on_event), Some(callback)) => {
+                                    on_event(&TimerEvent::Expired { timer_id, data: data.clone(), missed });
+                                    callback(timer_id, data);
+                                }
+                                (Some(on_event), None) => on_event(&TimerEvent::Expired { timer_id, data, missed }),
+                                (None, Some(callback)) => callback(timer_id, data),
+                                (None, None) => {}
+                            },
+                            Dispatch::Event(event) => {
+                                if let Some(on_event) = &on_event {
+                                    on_event(&event);
+                                }
                             }
-                        } else {
-                            let sleep_duration_ms = timer.get_time_left(now);
-                            let sleep_duration = Duration::from_millis(sleep_duration_ms);
-                            debug!("Waiting for timer to expire: {:?}", timer);
-                            thread::sleep(sleep_duration);
                         }
-                    } else {
-                        thread::sleep(Duration::from_millis(100));
                     }
-                }
+                }));
+                dispatch
             });
+
+            let (sender, queue) = mpsc::channel();
+            let mut scheduler =
+                Scheduler::new(state, shard_store, notifier.clone(), config.clock.clone(), dispatch, firing)
+                    .with_clock_jump_policy(config.clock_jump_policy)
+                    .with_missed_timer_policy(config.missed_timer_policy)
+                    .with_uptime_checkpoints(config.uptime_checkpoint_interval, uptime_shifted);
+            if let Some(feed) = &replication {
+                scheduler = scheduler.with_replication_feed(feed.clone());
+            }
+            workers.schedulers.push(thread::spawn(move || scheduler.run(queue)));
+            commands.push(sender);
+        }
+        if let (Some(primary), Some(keys)) = (config.follow, config.replication_key.clone()) {
+            workers.replicator = Some(Replicator::start(primary, keys, commands[0].clone()));
         }
+        let clock_waker: Arc<ClockWaker> = {
+            let commands = commands.clone();
+            Arc::new(move || {
+                for sender in &commands {
+                    let _ = sender.send(Command::Wake);
+                }
+            })
+        };
+        config.clock.subscribe(Arc::downgrade(&clock_waker));
+        workers.clock_waker = Some(clock_waker);
 
-        Ok(ts)
+        Ok(TimerShip {
+            store,
+            recovery_report: Arc::new(recovery_report),
+            clock: config.clock,
+            commands,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            notifier,
+            replication,
+            replication_key: config.replication_key,
+            workers: Arc::new(Mutex::new(workers)),
+        })
     }
 
-    /// Recovers timer state from operation logs
-    fn recover_from_logs(&self) -> std::io::Result<()> {
-        info!("Starting recovery from logs...");
-        let logs = self.oplog.read_logs()?;
-        let log_count = logs.len();
+    /// Stops firing timers and waits for callbacks already due to finish.
+    ///
+    /// No timers fire afterwards, but timers can still be set and removed.
+    /// Change streams end once they have caught up. The store, and with it any
+    /// lock on the log, is released once every clone of this `TimerShip` has
+    /// been dropped; a callback holding a clone keeps it alive until this is
+    /// called. Calling this more than once, or from inside the expiration
+    /// callback, is harmless.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        self.notifier.close();
+        if let Some(replicator) = self.workers().replicator.take() {
+            replicator.stop();
+        }
+        for stopped in self.submit_all(|reply| Command::StopFiring { reply }) {
+            if let Err(e) = stopped.wait() {
+                error!("Failed to stop firing timers: {}", e);
+            }
+        }
 
-        for entry in &logs {
-            match &entry.operation {
-                LogOperation::SetTimer {
-                    timer_id,
-                    expires_at,
-                    data,
-                } => {
-                    let timer = Timer::with_id(*expires_at, *timer_id);
-                    self.timer_data.add_data(*timer_id, data.clone());
-                    self.timers.add_timer(timer);
-                    debug!("Recovered SetTimer: ID {}, expires_at {}", timer_id, expires_at);
-                }
-                LogOperation::RemoveTimer { timer_id } => {
-                    self.timers.remove_timer(*timer_id);
-                    self.timer_data.remove_data(*timer_id);
-                    debug!("Recovered RemoveTimer: ID {}", timer_id);
-                }
+        // Taken out first so the callback can still use this instance while we wait
+        let dispatchers = std::mem::take(&mut self.workers().dispatchers);
+        for handle in dispatchers {
+            if handle.thread().id() != thread::current().id() && handle.join().is_err() {
+                error!("Timer callback thread panicked");
             }
         }
+    }
 
-        info!("Recovery completed. Processed {} log entries.", log_count);
+    fn workers(&self) -> std::sync::MutexGuard<'_, Workers> {
+        self.workers.lock().expect("Failed to lock timer threads")
+    }
+
+    /// Whether `shutdown` has been called
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Whether this instance is a follower that has not been promoted
+    pub fn is_follower(&self) -> bool {
+        self.workers().replicator.is_some()
+    }
+
+    /// Streams this instance's log to followers connecting on `addr`.
+    ///
+    /// Followers are `TimerShip`s created with `TimerShipConfig::follow` set to
+    /// this address and the same `replication_key`. A follower reconnecting
+    /// within this run gets only the records it missed; otherwise it gets a
+    /// snapshot of the pending timers. Needs `replication_key`, and is
+    /// unsupported with more than one shard or on an unpromoted follower.
+    pub fn serve_replication<A: ToSocketAddrs>(&self, addr: A) -> std::io::Result<ReplicationServer> {
+        self.ensure_single_shard("replication")?;
+        let (Some(feed), Some(keys)) = (&self.replication, &self.replication_key) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "serving replication needs TimerShipConfig::replication_key",
+            ));
+        };
+        if self.is_follower() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a follower cannot serve replication until it is promoted",
+            ));
+        }
+        feed.start_serving();
+        ReplicationServer::start(self.clone(), feed.clone(), keys.clone(), addr)
+    }
+
+    /// Pending timers as `SetTimer` records, and the last replicated sequence number they reflect
+    pub(crate) fn replica_snapshot(&self) -> std::io::Result<(u64, Vec<LogEntry>)> {
+        self.submit(0, |reply| Command::ReplicaSnapshot { reply }).wait()
+    }
+
+    /// Turns a follower into a primary.
+    ///
+    /// Stops replicating, writes the replicated timers into this instance's
+    /// own store, and starts firing timers and accepting writes. Only promote
+    /// a follower once its old primary is stopped, or both will fire.
+    pub fn promote(&self) -> std::io::Result<()> {
+        let replicator = self.workers().replicator.take();
+        let Some(replicator) = replicator else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "only a follower can be promoted",
+            ));
+        };
+        replicator.stop();
+
+        self.submit(0, |reply| Command::Promote { reply }).wait()?;
+        info!("Promoted to primary with {} timers", self.try_active_timer_count()?);
         Ok(())
     }
 
-    /// Gets the next timer to expire
+    /// Hands a command to the scheduler thread of `shard`
+    fn submit<T>(&self, shard: usize, command: impl FnOnce(Reply<T>) -> Command) -> Completion<T> {
+        let (reply, completion) = Completion::channel();
+        if self.commands[shard].send(command(reply)).is_err() {
+            return Completion::failed(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "timer scheduler has stopped",
+            ));
+        }
+        completion
+    }
+
+    /// Hands a command to every shard's scheduler, so they work on it in parallel
+    fn submit_all<T>(&self, command: impl Fn(Reply<T>) -> Command) -> Vec<Completion<T>> {
+        (0..self.commands.len()).map(|shard| self.submit(shard, &command)).collect()
+    }
+
+    /// Hands a change to the scheduler owning `timer_id` unless this instance is a follower
+    fn submit_change<T>(&self, timer_id: Uuid, command: impl FnOnce(Reply<T>) -> Command) -> Completion<T> {
+        match self.ensure_writable() {
+            Ok(()) => self.submit(shard_for(timer_id, self.commands.len()), command),
+            Err(e) => Completion::failed(e),
+        }
+    }
+
+    /// Fails if this instance is a follower, which only takes changes from its primary
+    fn ensure_writable(&self) -> std::io::Result<()> {
+        if self.is_follower() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "this TimerShip is a replication follower; promote it before making changes",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the retained set, remove and fire records with a sequence number above `seq`.
+    ///
+    /// Pass 0 to read everything retained, or the `seq` of the last record seen
+    /// to read only what is new. Unsupported with more than one shard.
+    pub fn changes_since(&self, seq: u64) -> std::io::Result<Vec<LogEntry>> {
+        self.ensure_single_shard("a change stream")?;
+        self.store.changes_since(seq)
+    }
+
+    /// Streams the records after `seq`, blocking for new ones as they are appended.
+    ///
+    /// Fails with `ErrorKind::Unsupported` with more than one shard.
+    pub fn watch(&self, seq: u64) -> std::io::Result<ChangeStream> {
+        self.ensure_single_shard("a change stream")?;
+        Ok(ChangeStream::new(self.store.clone(), self.notifier.clone(), seq))
+    }
+
+    /// Fails for features that need one sequence of records across all timers
+    fn ensure_single_shard(&self, feature: &str) -> std::io::Result<()> {
+        if self.commands.len() > 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} is not supported across more than one shard", feature),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Gets the report of the recovery run performed when this instance was created
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Compacts the persistent store down to the records of live timers
+    pub fn compact(&self) -> std::io::Result<CompactionReport> {
+        self.store.compact()
+    }
+
+    /// Writes a consistent point-in-time copy of the persistent state to `dest`.
+    ///
+    /// Timers can keep being set and removed while the copy is taken. Restore
+    /// a log backup with `restore_backup` or the `restore` command.
+    pub fn backup_to(&self, dest: &str) -> std::io::Result<BackupReport> {
+        self.store.backup_to(Path::new(dest))
+    }
+
+    /// Gets the next timer to expire, or `None` if a scheduler cannot be
+    /// reached; `try_get_expiring_timer` reports that failure instead
     pub fn get_expiring_timer(&self) -> Option<Timer> {
-        self.timers.peek_timer()
+        self.try_get_expiring_timer().unwrap_or_else(|e| {
+            error!("Failed to find the next timer: {}", e);
+            None
+        })
+    }
+
+    /// Gets the next timer to expire, failing if a scheduler cannot be reached
+    pub fn try_get_expiring_timer(&self) -> std::io::Result<Option<Timer>> {
+        let mut next_timers = Vec::with_capacity(self.commands.len());
+        for next in self.submit_all(|reply| Command::NextTimer { reply }) {
+            next_timers.extend(next.wait()?);
+        }
+        Ok(next_timers.into_iter().min_by_key(|timer| timer.expires_at))
     }
 
     /// Sets a new timer with associated data
     pub fn set_timer(&self, expires_at: u64, data: String) -> std::io::Result<Uuid> {
-        let new_timer = Timer::new(expires_at);
-        let timer_id = new_timer.id;
-
-        // Log the operation first
-        let log_entry = LogEntry {
-            timestamp: current_time_ms(),
-            operation: LogOperation::SetTimer {
-                timer_id,
-                expires_at,
-                data: data.clone(),
-            },
-        };
-        self.oplog.append_log(log_entry)?;
-
-        // Then apply the operation
-        self.timer_data.add_data(timer_id, data);
-        self.timers.add_timer(new_timer);
-
-        Ok(timer_id)
+        self.set_timer_at(expires_at, data)
     }
 
     /// Sets a new timer with duration string (e.g., "1.5s", "100ms", "2m")
     pub fn set_timer_with_duration(&self, duration_str: &str, data: String) -> Result<Uuid, Box<dyn std::error::Error>> {
         let duration_ms = parse_duration(duration_str)?;
-        let expires_at = current_time_ms() + duration_ms;
-        Ok(self.set_timer_at(expires_at, data)?)
+        let now = self.clock.now_ms();
+        Ok(self.schedule(Timer::new_at(now + duration_ms, now), data).wait()?)
     }
 
     /// Sets a new timer with absolute expiration time in milliseconds
     pub fn set_timer_at(&self, expires_at: u64, data: String) -> std::io::Result<Uuid> {
-        let new_timer = Timer::new(expires_at);
-        let timer_id = new_timer.id;
-
-        // Log the operation first
-        let log_entry = LogEntry {
-            timestamp: current_time_ms(),
-            operation: LogOperation::SetTimer {
-                timer_id,
-                expires_at,
-                data: data.clone(),
-            },
-        };
-        self.oplog.append_log(log_entry)?;
+        self.submit_set_timer_at(expires_at, data).wait()
+    }
+
+    /// Queues a new timer without waiting for it to be logged
+    pub fn submit_set_timer_at(&self, expires_at: u64, data: String) -> Completion<Uuid> {
+        self.submit_set_timer_with_options(expires_at, data, TimerOptions::default())
+    }
+
+    /// Sets a new timer with absolute expiration time in milliseconds and explicit options
+    pub fn set_timer_with_options(&self, expires_at: u64, data: String, options: TimerOptions) -> std::io::Result<Uuid> {
+        self.submit_set_timer_with_options(expires_at, data, options).wait()
+    }
 
-        // Then apply the operation
-        self.timer_data.add_data(timer_id, data);
-        self.timers.add_timer(new_timer);
+    /// Queues a new timer with explicit options without waiting for it to be logged
+    pub fn submit_set_timer_with_options(
+        &self,
+        expires_at: u64,
+        data: String,
+        options: TimerOptions,
+    ) -> Completion<Uuid> {
+        let timer = Timer::new_at(expires_at, self.clock.now_ms())
+            .with_priority(options.priority)
+            .with_uptime(options.uptime)
+            .with_key(options.key);
+        self.schedule(timer, data)
+    }
 
-        Ok(timer_id)
+    /// Hands a newly created timer to the scheduler
+    fn schedule(&self, timer: Timer, data: String) -> Completion<Uuid> {
+        self.submit_change(timer.id, |reply| Command::Set { timer, data, reply })
     }
 
     /// Removes a timer and returns its associated data
     pub fn remove_timer(&self, timer_id: Uuid) -> std::io::Result<Option<String>> {
-        // Log the operation first
-        let log_entry = LogEntry {
-            timestamp: current_time_ms(),
-            operation: LogOperation::RemoveTimer { timer_id },
-        };
-        self.oplog.append_log(log_entry)?;
+        self.submit_remove_timer(timer_id).wait()
+    }
 
-        // Then apply the operation
-        Ok(self.remove_timer_internal(timer_id))
+    /// Queues the removal of a timer without waiting for it to be logged
+    pub fn submit_remove_timer(&self, timer_id: Uuid) -> Completion<Option<String>> {
+        self.submit_change(timer_id, |reply| Command::Remove { timer_id, reply })
     }
 
-    fn remove_timer_internal(&self, timer_id: Uuid) -> Option<String> {
-        self.timers.remove_timer(timer_id);
-        let data = self.timer_data.remove_data(timer_id);
-        if let Some(ref data_str) = data {
-            debug!("Removed timer data: {}", data_str);
-        } else {
-            warn!("No data found for timer ID: {}", timer_id);
-        }
-        data
+    /// Moves an active timer to a new expiration time in milliseconds.
+    ///
+    /// Returns false, logging nothing, if the timer is not active.
+    pub fn reschedule_timer(&self, timer_id: Uuid, expires_at: u64) -> std::io::Result<bool> {
+        self.submit_reschedule_timer(timer_id, expires_at).wait()
+    }
+
+    /// Queues a reschedule without waiting for it to be logged
+    pub fn submit_reschedule_timer(&self, timer_id: Uuid, expires_at: u64) -> Completion<bool> {
+        self.submit_change(timer_id, |reply| Command::Reschedule {
+            timer_id,
+            expires_at,
+            reply,
+        })
     }
 
-    /// Lists all active timers with their information
+    /// Lists all active timers with their information, or none if a scheduler
+    /// cannot be reached; `try_list_active_timers` reports that failure instead
     pub fn list_active_timers(&self) -> Vec<TimerInfo> {
-        let current_time = current_time_ms();
+        self.try_list_active_timers().unwrap_or_else(|e| {
+            error!("Failed to list timers: {}", e);
+            Vec::new()
+        })
+    }
+
+    /// Lists all active timers with their information, failing if a scheduler cannot be reached
+    pub fn try_list_active_timers(&self) -> std::io::Result<Vec<TimerInfo>> {
+        let current_time = self.clock.now_ms();
         let mut timer_infos = Vec::new();
-        
-        // Get all timers using the new public method
-        let timers = self.timers.get_all_timers();
-        
-        for timer in timers {
-            if let Some(data) = self.get_timer_data(timer.id) {
-                let time_left = if timer.expires_at > current_time {
-                    timer.expires_at - current_time
-                } else {
-                    0
-                };
-                
-                timer_infos.push(TimerInfo {
-                    id: timer.id,
-                    expires_at: timer.expires_at,
-                    data,
-                    time_left_ms: time_left,
-                });
-            }
+        for snapshot in self.submit_all(|reply| Command::Snapshot { reply }) {
+            timer_infos.extend(
+                snapshot
+                    .wait()?
+                    .into_iter()
+                    .map(|(timer, data)| TimerInfo::new(&timer, data, current_time)),
+            );
         }
         
         // Sort by expiration time (soonest first)
-        timer_infos.sort_by(|a, b| a.expires_at.cmp(&b.expires_at));
+        timer_infos.sort_by_key(|info| info.expires_at);
         
-        timer_infos
+        Ok(timer_infos)
     }
     
-    /// Gets the data associated with a timer ID
-    fn get_timer_data(&self, timer_id: Uuid) -> Option<String> {
-        self.timer_data.get_data(timer_id)
+    /// Writes every active timer to `writer` in the given format and returns how many were written
+    pub fn export<W: Write>(&self, writer: W, format: ExportFormat) -> std::io::Result<usize> {
+        let timers: Vec<ExportedTimer> = self.try_list_active_timers()?.iter().map(ExportedTimer::from).collect();
+        write_timers(writer, &timers, format)?;
+        info!("Exported {} timers", timers.len());
+        Ok(timers.len())
     }
-    
-    /// Gets the count of active timers
+
+    /// Reads timers written by `export` and schedules them with their original IDs.
+    ///
+    /// Timers whose ID is already active are skipped, so importing the same file
+    /// twice is harmless. Returns how many timers were imported.
+    pub fn import<R: BufRead>(&self, reader: R, format: ExportFormat) -> std::io::Result<usize> {
+        self.ensure_writable()?;
+        let mut by_shard = vec![Vec::new(); self.commands.len()];
+        for timer in read_timers(reader, format)? {
+            by_shard[shard_for(timer.id, self.commands.len())].push(timer);
+        }
+        let pending: Vec<_> = by_shard
+            .into_iter()
+            .enumerate()
+            .map(|(shard, timers)| self.submit(shard, |reply| Command::Import { timers, reply }))
+            .collect();
+        let mut imported = 0;
+        for completion in pending {
+            imported += completion.wait()?;
+        }
+        info!("Imported {} timers", imported);
+        Ok(imported)
+    }
+
+    /// Gets the count of active timers, or 0 if a scheduler cannot be reached;
+    /// `try_active_timer_count` reports that failure instead
     pub fn active_timer_count(&self) -> usize {
-        self.timers.timer_count()
+        self.try_active_timer_count().unwrap_or_else(|e| {
+            error!("Failed to count timers: {}", e);
+            0
+        })
+    }
+
+    /// Gets the count of active timers, failing if a scheduler cannot be reached
+    pub fn try_active_timer_count(&self) -> std::io::Result<usize> {
+        self.submit_all(|reply| Command::Count { reply })
+            .into_iter()
+            .map(Completion::wait)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::TimerEvent;
+    use crate::persistence::{LogOperation, MemoryStore, MissedTimerPolicy};
+    use crate::utils::MockClock;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_state_recovered_from_store() {
+        let store = MemoryStore::new();
+        let timer_ship = TimerShip::with_store(store.clone(), None).unwrap();
+        let kept = timer_ship.set_timer_with_duration("1h", "kept".to_string()).unwrap();
+        let removed = timer_ship.set_timer_with_duration("1h", "removed".to_string()).unwrap();
+        timer_ship.remove_timer(removed).unwrap();
+
+        let recovered = TimerShip::with_store(store.clone(), None).unwrap();
+        let report = recovered.recovery_report();
+        assert_eq!(report.entries_replayed, 3);
+        assert_eq!(report.live_timers, 1);
+        assert_eq!(report.overdue_timers, 0);
+
+        let timers = recovered.list_active_timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].id, kept);
+        assert_eq!(timers[0].data, "kept");
+
+        // The kept timer and the compaction marker
+        let report = recovered.compact().unwrap();
+        assert_eq!(report.entries_after, 2);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_export_import_preserves_ids() {
+        let source = TimerShip::with_store(MemoryStore::new(), None).unwrap();
+        let timer_id = source.set_timer_with_duration("1h", "session:alice".to_string()).unwrap();
+
+        let mut exported = Vec::new();
+        assert_eq!(source.export(&mut exported, ExportFormat::Csv).unwrap(), 1);
+
+        let target = TimerShip::with_store(MemoryStore::new(), None).unwrap();
+        assert_eq!(target.import(&exported[..], ExportFormat::Csv).unwrap(), 1);
+        assert_eq!(target.import(&exported[..], ExportFormat::Csv).unwrap(), 0);
+
+        let timers = target.list_active_timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].id, timer_id);
+        assert_eq!(timers[0].data, "session:alice");
+    }
+
+    #[test]
+    fn test_watch_streams_changes_in_order() {
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::with_config(MemoryStore::new(), None, config).unwrap();
+        let mut stream = timer_ship.watch(0).unwrap();
+
+        let removed = timer_ship.set_timer_with_duration("1h", "removed".to_string()).unwrap();
+        timer_ship.remove_timer(removed).unwrap();
+        let fired = timer_ship.set_timer_with_duration("10ms", "fired".to_string()).unwrap();
+        clock.advance(Duration::from_millis(10));
+
+        let mut seen = Vec::new();
+        while let Some(entry) = stream.next_timeout(Duration::from_secs(2)).unwrap() {
+            seen.push(entry);
+            if seen.len() == 4 {
+                break;
+            }
+        }
+        assert!(matches!(seen[0].operation, LogOperation::SetTimer { timer_id, .. } if timer_id == removed));
+        assert!(matches!(seen[1].operation, LogOperation::RemoveTimer { timer_id } if timer_id == removed));
+        assert!(matches!(seen[2].operation, LogOperation::SetTimer { timer_id, .. } if timer_id == fired));
+        assert!(matches!(seen[3].operation, LogOperation::FireTimer { timer_id } if timer_id == fired));
+        assert!(seen.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+
+        assert_eq!(timer_ship.changes_since(seen[1].seq).unwrap().len(), 2);
+        timer_ship.shutdown();
+        assert!(stream.next().is_none());
+        assert_eq!(stream.cursor(), seen[3].seq);
+    }
+
+    #[test]
+    fn test_timing_wheel_queue_fires_in_order() {
+        let (callback, fired) = fired_channel();
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            queue: QueueKind::TimingWheel,
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::with_config(MemoryStore::new(), Some(callback), config).unwrap();
+        let cancelled = timer_ship.set_timer_with_duration("30ms", "cancelled".to_string()).unwrap();
+        timer_ship.set_timer_with_duration("1h", "pending".to_string()).unwrap();
+        timer_ship.set_timer_with_duration("60ms", "second".to_string()).unwrap();
+        timer_ship.set_timer_with_duration("20ms", "first".to_string()).unwrap();
+        timer_ship.remove_timer(cancelled).unwrap();
+        assert!(fired.try_recv().is_err());
+
+        clock.advance(Duration::from_millis(60));
+        assert_eq!(receive(&fired, 2), ["first", "second"]);
+        assert_eq!(timer_ship.active_timer_count(), 1);
+        timer_ship.shutdown();
+    }
+
+    #[test]
+    fn test_reschedule_is_logged_and_recovered() {
+        let store = MemoryStore::new();
+        let (callback, fired) = fired_channel();
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::with_config(store.clone(), Some(callback), config).unwrap();
+        let sooner = timer_ship.set_timer_with_duration("1h", "sooner".to_string()).unwrap();
+        let later = timer_ship.set_timer_with_duration("1h", "later".to_string()).unwrap();
+
+        let moved = timer_ship.submit_reschedule_timer(sooner, clock.now_ms() + 60_000);
+        assert!(moved.wait().unwrap());
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(receive(&fired, 1), ["sooner"]);
+        assert!(!timer_ship.reschedule_timer(sooner, clock.now_ms()).unwrap());
+
+        let expires_at = clock.now_ms() + 7_200_000;
+        assert!(timer_ship.reschedule_timer(later, expires_at).unwrap());
+        timer_ship.shutdown();
+        drop(timer_ship);
+
+        let recovered = TimerShip::with_store(store, None).unwrap();
+        let timers = recovered.list_active_timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!((timers[0].id, timers[0].expires_at), (later, expires_at));
+        // The original duration survives the reschedule
+        assert_eq!(timers[0].duration_ms, 3_600_000);
+    }
+
+    #[test]
+    fn test_sharded_timers_aggregate_and_recover() {
+        let shards = vec![MemoryStore::new(), MemoryStore::new(), MemoryStore::new()];
+        let store = ShardedStore::new(shards.clone()).unwrap();
+        let (callback, fired) = fired_channel();
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::sharded(store.clone(), Some(callback), config).unwrap();
+        let mut pending = Vec::new();
+        for i in 0..30 {
+            pending.push(timer_ship.set_timer_with_duration("1h", format!("timer {}", i)).unwrap());
+        }
+        timer_ship.set_timer_with_duration("10ms", "fired".to_string()).unwrap();
+        timer_ship.remove_timer(pending.pop().unwrap()).unwrap();
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(receive(&fired, 1), ["fired"]);
+        assert_eq!(timer_ship.active_timer_count(), 29);
+        assert_eq!(timer_ship.list_active_timers().len(), 29);
+        assert!(shards.iter().all(|shard| !shard.is_empty()));
+        fn unsupported<T>(result: std::io::Result<T>) -> bool {
+            result.is_err_and(|e| e.kind() == std::io::ErrorKind::Unsupported)
+        }
+        assert!(unsupported(timer_ship.changes_since(0)));
+        assert!(unsupported(timer_ship.watch(0)));
+        assert!(unsupported(timer_ship.serve_replication("127.0.0.1:0")));
+        drop(timer_ship);
+
+        let recovered = TimerShip::sharded(store, None, TimerShipConfig::default()).unwrap();
+        assert_eq!(recovered.recovery_report().live_timers, 29);
+        assert!(recovered.remove_timer(pending[0]).unwrap().is_some());
+
+        let resharded = ShardedStore::new(shards[..2].to_vec()).unwrap();
+        recovered.shutdown();
+        drop(recovered);
+        assert!(TimerShip::sharded(resharded, None, TimerShipConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_equal_deadlines_fire_by_priority_then_fifo() {
+        let (callback, fired) = fired_channel();
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::with_config(MemoryStore::new(), Some(callback), config).unwrap();
+        let expires_at = clock.now_ms() + 1_000;
+        let mut expected = Vec::new();
+        for i in 0..20 {
+            let priority = if i % 5 == 0 { 9 } else { 0 };
+            let data = format!("{}:{}", priority, i);
+            timer_ship
+                .set_timer_with_options(expires_at, data.clone(), TimerOptions { priority, ..TimerOptions::default() })
+                .unwrap();
+            expected.push((priority, data));
+        }
+        expected.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+
+        clock.advance(Duration::from_secs(1));
+        let expected: Vec<String> = expected.into_iter().map(|(_, data)| data).collect();
+        assert_eq!(receive(&fired, 20), expected);
+        timer_ship.shutdown();
+    }
+
+    #[test]
+    fn test_uptime_timers_pause_while_down() {
+        let store = MemoryStore::new();
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let set_at = clock.now_ms();
+        let timer_ship = TimerShip::with_config(store.clone(), None, config.clone()).unwrap();
+        let options = TimerOptions {
+            uptime: true,
+            ..TimerOptions::default()
+        };
+        let uptime = timer_ship
+            .set_timer_with_options(set_at + 3_600_000, "uptime".to_string(), options)
+            .unwrap();
+        let wall = timer_ship.set_timer_with_duration("1h", "wall".to_string()).unwrap();
+        clock.advance(Duration::from_secs(600));
+        timer_ship.shutdown();
+        drop(timer_ship);
+
+        // Down for half an hour, which wall-clock timers count and uptime timers do not
+        clock.set_ms(clock.now_ms() + 1_800_000);
+        for _ in 0..2 {
+            let recovered = TimerShip::with_config(store.clone(), None, config.clone()).unwrap();
+            let expires_at: HashMap<Uuid, u64> = recovered
+                .list_active_timers()
+                .into_iter()
+                .map(|info| (info.id, info.expires_at))
+                .collect();
+            assert_eq!(expires_at[&uptime], set_at + 5_400_000);
+            assert_eq!(expires_at[&wall], set_at + 3_600_000);
+            recovered.shutdown();
+        }
+    }
+
+    #[test]
+    fn test_missed_timers_fire_latest_per_key() {
+        let store = MemoryStore::new();
+        let clock = MockClock::starting_now();
+        let set_at = clock.now_ms();
+        let timer_ship = TimerShip::with_config(
+            store.clone(),
+            None,
+            TimerShipConfig {
+                clock: Arc::new(clock.clone()),
+                ..TimerShipConfig::default()
+            },
+        )
+        .unwrap();
+        let keyed = TimerOptions {
+            key: Some("order-17".to_string()),
+            ..TimerOptions::default()
+        };
+        let stale = timer_ship.set_timer_with_options(set_at + 1_000, "a".to_string(), keyed.clone()).unwrap();
+        let latest = timer_ship.set_timer_with_options(set_at + 2_000, "a".to_string(), keyed).unwrap();
+        // Same data but no key, so it is not grouped with the others
+        let other = timer_ship.set_timer_at(set_at + 3_000, "a".to_string()).unwrap();
+        timer_ship.set_timer_at(set_at + 3_600_000, "pending".to_string()).unwrap();
+        timer_ship.shutdown();
+        drop(timer_ship);
+
+        clock.set_ms(set_at + 10_000);
+        let (callback, fired) = fired_channel();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let unobserved = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            missed_timer_policy: MissedTimerPolicy::Skip,
+            ..TimerShipConfig::default()
+        };
+        let err = TimerShip::with_config(store.clone(), None, unobserved).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            missed_timer_policy: MissedTimerPolicy::FireLatestPerKey,
+            on_event: Some({
+                let events = events.clone();
+                Arc::new(move |event: &TimerEvent| events.lock().unwrap().push(event.clone()))
+            }),
+            ..TimerShipConfig::default()
+        };
+        let recovered = TimerShip::with_config(store, Some(callback), config).unwrap();
+        let report = recovered.recovery_report();
+        assert_eq!(report.overdue_timers, 3);
+        assert_eq!((report.max_overdue_ms, report.total_overdue_ms), (9_000, 24_000));
+
+        let fresh = recovered.set_timer_with_duration("1s", "fresh".to_string()).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(receive(&fired, 3), ["a", "a", "fresh"]);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                TimerEvent::DeadLettered {
+                    timer_id: stale,
+                    data: "a".to_string(),
+                    overdue_ms: 9_000,
+                },
+                TimerEvent::Expired {
+                    timer_id: latest,
+                    data: "a".to_string(),
+                    missed: true,
+                },
+                TimerEvent::Expired {
+                    timer_id: other,
+                    data: "a".to_string(),
+                    missed: true,
+                },
+                TimerEvent::Expired {
+                    timer_id: fresh,
+                    data: "fresh".to_string(),
+                    missed: false,
+                },
+            ]
+        );
+        assert_eq!(recovered.active_timer_count(), 1);
+        recovered.shutdown();
+    }
+
+    #[test]
+    fn test_clock_jump_preserves_durations() {
+        let store = MemoryStore::new();
+        let (callback, fired) = fired_channel();
+        let (event_sender, events) = mpsc::channel();
+        let clock = MockClock::starting_now();
+        let config = TimerShipConfig {
+            clock: Arc::new(clock.clone()),
+            clock_jump_policy: ClockJumpPolicy::PreserveDurations,
+            on_event: Some(Arc::new(move |event: &TimerEvent| {
+                let _ = event_sender.send(event.clone());
+            })),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::with_config(store.clone(), Some(callback), config).unwrap();
+        timer_ship.set_timer_with_duration("10s", "short".to_string()).unwrap();
+        let long = timer_ship.set_timer_with_duration("1h", "long".to_string()).unwrap();
+
+        clock.set_ms(clock.now_ms() - 3_600_000);
+        assert_eq!(
+            receive(&events, 1)[0],
+            TimerEvent::ClockJump {
+                jump_ms: -3_600_000,
+                policy: ClockJumpPolicy::PreserveDurations,
+                timers_shifted: 2,
+            }
+        );
+        let logged = store.read_all().unwrap().entries;
+        let shifts: Vec<_> = logged
+            .iter()
+            .filter(|entry| !matches!(entry.operation, LogOperation::SetTimer { .. }))
+            .collect();
+        assert!(matches!(
+            shifts[..],
+            [LogEntry { operation: LogOperation::ShiftDeadlines { offset_ms: -3_600_000 }, .. }]
+        ));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(receive(&fired, 1), ["short"]);
+        let expires_at = clock.now_ms() + 3_590_000;
+        timer_ship.shutdown();
+        drop(timer_ship);
+
+        let recovered = TimerShip::with_store(store, None).unwrap();
+        let timers = recovered.list_active_timers();
+        assert_eq!((timers[0].id, timers[0].expires_at), (long, expires_at));
+    }
+
+    /// Expiration callback sending the data of each fired timer to the returned receiver
+    fn fired_channel() -> (TimerCallback, mpsc::Receiver<String>) {
+        let (sender, receiver) = mpsc::channel();
+        let callback: TimerCallback = Box::new(move |_, data| {
+            let _ = sender.send(data);
+        });
+        (callback, receiver)
+    }
+
+    /// Blocks for the next `count` values, failing if they do not arrive
+    #[track_caller]
+    fn receive<T>(receiver: &mpsc::Receiver<T>, count: usize) -> Vec<T> {
+        (0..count)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).expect("value not received in time"))
+            .collect()
+    }
+
+    /// Waits until a follower's timers satisfy `caught_up`. Replication
+    /// arrives over TCP with nothing to block on, so this is the one wait that polls.
+    #[track_caller]
+    fn wait_for_replica(follower: &TimerShip, caught_up: impl Fn(&[TimerInfo]) -> bool) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !caught_up(&follower.list_active_timers()) {
+            assert!(std::time::Instant::now() < deadline, "follower did not catch up in time");
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_follower_replicates_and_promotes() {
+        let unkeyed = TimerShip::with_store(MemoryStore::new(), None).unwrap();
+        assert!(unkeyed.serve_replication("127.0.0.1:0").is_err());
+
+        let clock = MockClock::starting_now();
+        let keyed = || TimerShipConfig {
+            replication_key: Some(KeyRing::new(1, [5; 32])),
+            clock: Arc::new(clock.clone()),
+            ..TimerShipConfig::default()
+        };
+        let (primary_callback, primary_fired) = fired_channel();
+        let primary = TimerShip::with_config(MemoryStore::new(), Some(primary_callback), keyed()).unwrap();
+        let mut server = primary.serve_replication("127.0.0.1:0").unwrap();
+        let kept = primary.set_timer_with_duration("1h", "kept".to_string()).unwrap();
+
+        let (callback, fired) = fired_channel();
+        let config = TimerShipConfig {
+            follow: Some(server.local_addr().to_string()),
+            ..keyed()
+        };
+        let store = MemoryStore::new();
+        let follower = TimerShip::with_config(store.clone(), Some(callback), config).unwrap();
+        assert!(follower.is_follower());
+
+        let removed = primary.set_timer_with_duration("1h", "removed".to_string()).unwrap();
+        primary.remove_timer(removed).unwrap();
+        primary.set_timer_with_duration("20ms", "fires on primary".to_string()).unwrap();
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(receive(&primary_fired, 1), ["fires on primary"]);
+        wait_for_replica(&follower, |timers| timers.iter().map(|info| info.id).eq([kept]));
+
+        let err = follower.set_timer_with_duration("1h", "refused".to_string()).unwrap_err();
+        assert!(err.to_string().contains("follower"));
+        assert!(store.is_empty());
+
+        server.stop();
+        primary.shutdown();
+        follower.promote().unwrap();
+        assert!(!follower.is_follower());
+        assert_eq!(store.len(), 1);
+        follower.set_timer_with_duration("10ms", "promoted".to_string()).unwrap();
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(receive(&fired, 1), ["promoted"]);
+        follower.shutdown();
+    }
+
+    #[test]
+    fn test_promoted_log_verifies() {
+        let dir = std::env::temp_dir().join(format!("timer_ship_promote_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let config = TimerShipConfig {
+            replication_key: Some(KeyRing::new(1, [5; 32])),
+            ..TimerShipConfig::default()
+        };
+        let primary = TimerShip::open(&path("primary.log"), None, config.clone()).unwrap();
+        let mut server = primary.serve_replication("127.0.0.1:0").unwrap();
+        let timer_id = primary.set_timer_with_duration("1h", "moved".to_string()).unwrap();
+
+        // The follower's log already holds the timer, which then moves on the primary
+        primary.backup_to(&path("follower.log")).unwrap();
+        let expires_at = primary.list_active_timers()[0].expires_at + 60_000;
+        assert!(primary.reschedule_timer(timer_id, expires_at).unwrap());
+        let follower_config = TimerShipConfig {
+            follow: Some(server.local_addr().to_string()),
+            ..config
+        };
+        let follower = TimerShip::open(&path("follower.log"), None, follower_config).unwrap();
+        wait_for_replica(&follower, |timers| timers.iter().any(|info| info.expires_at == expires_at));
+
+        server.stop();
+        primary.shutdown();
+        follower.promote().unwrap();
+        follower.shutdown();
+        drop(follower);
+
+        let log = OpLog::open_read_only(&path("follower.log")).unwrap();
+        let report = crate::persistence::verify_log(&log).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        drop((primary, log));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_compresses_compacted_segments() {
+        let dir = std::env::temp_dir().join(format!("timer_ship_open_{}/", Uuid::new_v4()));
+        let config = TimerShipConfig {
+            compress_segments: true,
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::open(dir.to_str().unwrap(), None, config).unwrap();
+        timer_ship.set_timer_with_duration("1h", "kept".to_string()).unwrap();
+        timer_ship.compact().unwrap();
+        timer_ship.shutdown();
+        drop(timer_ship);
+
+        let names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.iter().any(|name| name.ends_with(".log.gz")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_encrypts_payloads() {
+        let path = std::env::temp_dir().join(format!("timer_ship_encrypted_{}.log", Uuid::new_v4()));
+        let log_path = path.to_str().unwrap();
+        let config = TimerShipConfig {
+            encryption_key: Some(KeyRing::new(1, [7; 32])),
+            ..TimerShipConfig::default()
+        };
+        let timer_ship = TimerShip::open(log_path, None, config.clone()).unwrap();
+        timer_ship.set_timer_with_duration("1h", "secret payload".to_string()).unwrap();
+        timer_ship.shutdown();
+        drop(timer_ship);
+
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret payload"));
+        let reopened = TimerShip::open(log_path, None, config).unwrap();
+        assert_eq!(reopened.list_active_timers()[0].data, "secret payload");
+        reopened.shutdown();
+        drop(reopened);
+        let unkeyed = TimerShip::open(log_path, None, TimerShipConfig::default()).unwrap();
+        assert!(unkeyed.list_active_timers()[0].data.starts_with("enc:"));
+        unkeyed.shutdown();
+        drop(unkeyed);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}.lock", log_path)).unwrap();
     }
 }
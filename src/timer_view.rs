@@ -0,0 +1,107 @@
+use crate::{
+    core::TimerState,
+    persistence::{OpLog, RecoveryManager, RecoveryPolicy, RecoveryReport, RecoveryTarget, TimerStore},
+    timer_ship::TimerInfo,
+};
+use std::sync::Arc;
+
+/// Read-only view of the timers that were live at a point in a timer log.
+///
+/// Opening a view replays the log without starting a timer thread, so nothing
+/// fires and nothing is written.
+#[derive(Debug, Clone)]
+pub struct TimerView {
+    timers: Vec<TimerInfo>,
+    report: RecoveryReport,
+}
+
+impl TimerView {
+    /// Opens a view of the log file or segment directory at `log_path`
+    pub fn open(log_path: &str, target: RecoveryTarget) -> std::io::Result<Self> {
+        Self::from_store(Arc::new(OpLog::open_read_only(log_path)?), target)
+    }
+
+    /// Opens a view of any timer store
+    pub fn from_store(store: Arc<dyn TimerStore>, target: RecoveryTarget) -> std::io::Result<Self> {
+        let mut state = TimerState::default();
+        let report = RecoveryManager::new(store).recover_to(&mut state, RecoveryPolicy::Lenient, target)?;
+
+        let mut infos: Vec<TimerInfo> = state
+            .snapshot()
+            .into_iter()
+            .map(|(timer, data)| TimerInfo::new(&timer, data, report.as_of))
+            .collect();
+        infos.sort_by_key(|info| info.expires_at);
+
+        Ok(TimerView {
+            timers: infos,
+            report,
+        })
+    }
+
+    /// The moment this view represents, in milliseconds since UNIX epoch
+    pub fn as_of(&self) -> u64 {
+        self.report.as_of
+    }
+
+    /// Timers live at that moment, soonest expiration first.
+    /// `time_left_ms` and `elapsed_ms` are measured from `as_of`.
+    pub fn timers(&self) -> &[TimerInfo] {
+        &self.timers
+    }
+
+    /// Details of the replay that produced this view
+    pub fn report(&self) -> &RecoveryReport {
+        &self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{LogEntry, LogOperation, MemoryStore};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_view_at_timestamp() {
+        let store = MemoryStore::new();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let set = |timer_id| LogOperation::SetTimer {
+            timer_id,
+            expires_at: 10_000,
+            data: timer_id.to_string(),
+            created_at: 0,
+            duration_ms: 10_000,
+            priority: 0,
+            uptime: false,
+            key: None,
+        };
+        store.append(LogEntry::new(100, set(first))).unwrap();
+        store.append(LogEntry::new(200, set(second))).unwrap();
+        store
+            .append(LogEntry::new(300, LogOperation::RemoveTimer { timer_id: first }))
+            .unwrap();
+
+        let store: Arc<dyn TimerStore> = Arc::new(store);
+        let at_250 = TimerView::from_store(store.clone(), RecoveryTarget::Timestamp(250)).unwrap();
+        assert_eq!(at_250.timers().len(), 2);
+        assert_eq!(at_250.timers()[0].time_left_ms, 9_750);
+        assert_eq!(at_250.timers()[0].elapsed_ms, 250);
+        assert_eq!(at_250.timers()[0].percent_complete(), 2.5);
+
+        let latest = TimerView::from_store(store.clone(), RecoveryTarget::Latest).unwrap();
+        assert_eq!(latest.timers().len(), 1);
+        assert_eq!(latest.timers()[0].id, second);
+
+        let first_entry = TimerView::from_store(store.clone(), RecoveryTarget::EntryIndex(1)).unwrap();
+        assert_eq!(first_entry.as_of(), 100);
+        assert_eq!(first_entry.timers()[0].id, first);
+
+        store.compact().unwrap();
+        let err = TimerView::from_store(store.clone(), RecoveryTarget::Timestamp(250)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let at_300 = TimerView::from_store(store, RecoveryTarget::Timestamp(300)).unwrap();
+        assert_eq!(at_300.timers().len(), 1);
+    }
+}
@@ -0,0 +1,293 @@
+//! Export and import of live timers, for moving timers between hosts.
+
+use crate::{
+    persistence::{LogEntry, LogOperation},
+    timer_ship::TimerInfo,
+};
+use std::{
+    io::{BufRead, Write},
+    str::FromStr,
+};
+use uuid::Uuid;
+
+/// File formats supported for export and import
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A JSON array of timer objects
+    Json,
+    /// CSV with an `id,expires_at,created_at,priority,uptime,key,data` header row
+    Csv,
+}
+
+impl ExportFormat {
+    /// Picks a format from a file name's extension, defaulting to JSON
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".csv") {
+            ExportFormat::Csv
+        } else {
+            ExportFormat::Json
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+/// A live timer as written to an export file
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExportedTimer {
+    pub id: Uuid,
+    pub expires_at: u64,
+    /// When the timer was originally set, if known
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    /// Priority among timers due in the same millisecond
+    #[serde(default)]
+    pub priority: u8,
+    /// Whether the timer counts only uptime
+    #[serde(default)]
+    pub uptime: bool,
+    /// Key grouping the timer for missed-timer handling
+    #[serde(default)]
+    pub key: Option<String>,
+    pub data: String,
+}
+
+impl ExportedTimer {
+    /// Builds the log entry that recreates this timer.
+    ///
+    /// Timers without a recorded creation time are treated as created at `timestamp`.
+    pub fn to_log_entry(&self, timestamp: u64) -> LogEntry {
+        let created_at = self.created_at.unwrap_or(timestamp);
+        LogEntry::new(
+            timestamp,
+            LogOperation::SetTimer {
+                timer_id: self.id,
+                expires_at: self.expires_at,
+                data: self.data.clone(),
+                created_at,
+                duration_ms: self.expires_at.saturating_sub(created_at),
+                priority: self.priority,
+                uptime: self.uptime,
+                key: self.key.clone(),
+            },
+        )
+    }
+}
+
+impl From<&TimerInfo> for ExportedTimer {
+    fn from(info: &TimerInfo) -> Self {
+        ExportedTimer {
+            id: info.id,
+            expires_at: info.expires_at,
+            created_at: Some(info.created_at),
+            priority: info.priority,
+            uptime: info.uptime,
+            key: info.key.clone(),
+            data: info.data.clone(),
+        }
+    }
+}
+
+const CSV_HEADER: [&str; 7] = ["id", "expires_at", "created_at", "priority", "uptime", "key", "data"];
+
+/// Writes timers in the given format
+pub fn write_timers<W: Write>(mut writer: W, timers: &[ExportedTimer], format: ExportFormat) -> std::io::Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, timers)?;
+            writeln!(writer)?;
+        }
+        ExportFormat::Csv => {
+            writeln!(writer, "{}", CSV_HEADER.join(","))?;
+            for timer in timers {
+                let created_at = timer.created_at.map(|ms| ms.to_string()).unwrap_or_default();
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    timer.id,
+                    timer.expires_at,
+                    created_at,
+                    timer.priority,
+                    timer.uptime,
+                    csv_escape(timer.key.as_deref().unwrap_or_default()),
+                    csv_escape(&timer.data)
+                )?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// Reads timers written by `write_timers`
+pub fn read_timers<R: BufRead>(mut reader: R, format: ExportFormat) -> std::io::Result<Vec<ExportedTimer>> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::from_reader(reader)?),
+        ExportFormat::Csv => {
+            let mut input = String::new();
+            reader.read_to_string(&mut input)?;
+            let mut rows = parse_csv(&input)?.into_iter();
+
+            match rows.next() {
+                Some(header) if header == CSV_HEADER => {}
+                header => {
+                    return Err(invalid_data(format!(
+                        "unsupported CSV header {:?}, expected {}",
+                        header.map(|fields| fields.join(",")).unwrap_or_default(),
+                        CSV_HEADER.join(",")
+                    )));
+                }
+            }
+
+            rows.enumerate()
+                .map(|(index, row)| {
+                    let line = index + 2;
+                    let [id, expires_at, created_at, priority, uptime, key, data] = row.as_slice() else {
+                        return Err(invalid_data(format!(
+                            "row {}: expected {} fields, found {}",
+                            line,
+                            CSV_HEADER.len(),
+                            row.len()
+                        )));
+                    };
+
+                    Ok(ExportedTimer {
+                        id: id
+                            .parse()
+                            .map_err(|e| invalid_data(format!("row {}: invalid id: {}", line, e)))?,
+                        expires_at: expires_at
+                            .parse()
+                            .map_err(|e| invalid_data(format!("row {}: invalid expires_at: {}", line, e)))?,
+                        created_at: Some(created_at)
+                            .filter(|field| !field.is_empty())
+                            .map(|field| field.parse())
+                            .transpose()
+                            .map_err(|e| invalid_data(format!("row {}: invalid created_at: {}", line, e)))?,
+                        priority: priority
+                            .parse()
+                            .map_err(|e| invalid_data(format!("row {}: invalid priority: {}", line, e)))?,
+                        uptime: uptime
+                            .parse()
+                            .map_err(|e| invalid_data(format!("row {}: invalid uptime: {}", line, e)))?,
+                        key: Some(key).filter(|field| !field.is_empty()).cloned(),
+                        data: data.clone(),
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits CSV input into rows of fields, honouring quoted fields with `""` escapes and skipping blank lines
+fn parse_csv(input: &str) -> std::io::Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match (ch, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) if row.is_empty() && field.is_empty() => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(ch),
+        }
+    }
+
+    if in_quotes {
+        return Err(invalid_data("unterminated quoted CSV field".to_string()));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<ExportedTimer> {
+        vec![
+            ExportedTimer {
+                id: Uuid::new_v4(),
+                expires_at: 1000,
+                created_at: Some(500),
+                priority: 3,
+                uptime: true,
+                key: Some("order-17".to_string()),
+                data: "plain".to_string(),
+            },
+            ExportedTimer {
+                id: Uuid::new_v4(),
+                expires_at: 2000,
+                created_at: None,
+                priority: 0,
+                uptime: false,
+                key: None,
+                data: "user@example.com, \"quoted\"\nsecond line".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_both_formats() {
+        for format in [ExportFormat::Json, ExportFormat::Csv] {
+            let mut buffer = Vec::new();
+            write_timers(&mut buffer, &sample()[..], format).unwrap();
+            let timers = read_timers(&buffer[..], format).unwrap();
+            assert_eq!(timers.len(), 2);
+            assert_eq!(timers[1].data, sample()[1].data);
+            assert_eq!(timers[0].created_at, Some(500));
+            assert_eq!(timers[1].created_at, None);
+            assert_eq!(timers[0].priority, 3);
+            assert!(timers[0].uptime);
+            assert_eq!(timers[0].key.as_deref(), Some("order-17"));
+            assert_eq!(timers[1].key, None);
+        }
+
+        // Trailing blank lines, as left by editors, are ignored
+        let csv = "id,expires_at,created_at,priority,uptime,key,data\r\n67e55044-10b1-426f-9247-bb680e5fe0c8,1000,,0,false,,plain\r\n\r\n";
+        let timers = read_timers(csv.as_bytes(), ExportFormat::Csv).unwrap();
+        assert_eq!(timers[0].created_at, None);
+        assert_eq!(timers[0].data, "plain");
+
+        let unknown_header = "id,expires_at,data\n67e55044-10b1-426f-9247-bb680e5fe0c8,1000,old\n";
+        let error = read_timers(unknown_header.as_bytes(), ExportFormat::Csv).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
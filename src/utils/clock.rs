@@ -0,0 +1,170 @@
+use std::{
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Called when a clock's time changes other than by real time passing
+pub type ClockWaker = dyn Fn() + Send + Sync;
+
+/// Source of wall-clock time for a `TimerShip`.
+///
+/// The scheduler sleeps in real time until the next timer is due by this
+/// clock, so a clock that can jump must wake it through `subscribe`.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Current time in milliseconds since UNIX epoch
+    fn now_ms(&self) -> u64;
+
+    /// Milliseconds from an arbitrary fixed point, never affected by the wall
+    /// clock being stepped. Clock jumps are detected by comparing the two.
+    ///
+    /// Defaults to `now_ms`, which reports no jumps.
+    fn monotonic_ms(&self) -> u64 {
+        self.now_ms()
+    }
+
+    /// Registers a waker to call whenever the time is moved by hand.
+    ///
+    /// The clock keeps only a weak reference, so dropping the waker unregisters it.
+    fn subscribe(&self, _waker: Weak<ClockWaker>) {}
+}
+
+/// The system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        current_time_ms()
+    }
+
+    /// Does not advance while the machine is suspended, so resuming looks like a forward jump
+    fn monotonic_ms(&self) -> u64 {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_millis() as u64
+    }
+}
+
+/// How pending timers are treated when the wall clock jumps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockJumpPolicy {
+    /// Timers keep their absolute deadlines: a backward jump delays them and a
+    /// forward jump fires everything it skipped over
+    #[default]
+    KeepDeadlines,
+    /// Timers move with the clock, so each keeps the time it had left
+    PreserveDurations,
+}
+
+/// Clock that only moves when told to, for tests.
+///
+/// Clones share the same time, so a test can keep one and hand another to a `TimerShip`.
+#[derive(Clone, Default)]
+pub struct MockClock {
+    inner: Arc<Mutex<MockTime>>,
+}
+
+#[derive(Default)]
+struct MockTime {
+    now_ms: u64,
+    monotonic_ms: u64,
+    wakers: Vec<Weak<ClockWaker>>,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at `now_ms` (milliseconds since UNIX epoch)
+    pub fn new(now_ms: u64) -> Self {
+        let clock = MockClock::default();
+        clock.inner.lock().expect("Failed to lock mock clock").now_ms = now_ms;
+        clock
+    }
+
+    /// Creates a clock stopped at the current system time
+    pub fn starting_now() -> Self {
+        Self::new(current_time_ms())
+    }
+
+    /// Moves the clock forward by `by`, as if that much time had passed
+    pub fn advance(&self, by: Duration) {
+        let by = by.as_millis() as u64;
+        self.update(|time| {
+            time.now_ms += by;
+            time.monotonic_ms += by;
+        });
+    }
+
+    /// Steps the wall time to `now_ms` without any time passing, like an NTP
+    /// correction. It may be earlier than the current time.
+    pub fn set_ms(&self, now_ms: u64) {
+        self.update(|time| time.now_ms = now_ms);
+    }
+
+    fn update(&self, change: impl FnOnce(&mut MockTime)) {
+        let wakers: Vec<_> = {
+            let mut time = self.inner.lock().expect("Failed to lock mock clock");
+            change(&mut time);
+            time.wakers.retain(|waker| waker.strong_count() > 0);
+            time.wakers.iter().filter_map(Weak::upgrade).collect()
+        };
+        // Called without the lock, so a waker may read the clock
+        for wake in wakers {
+            wake();
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.inner.lock().expect("Failed to lock mock clock").now_ms
+    }
+
+    fn monotonic_ms(&self) -> u64 {
+        self.inner.lock().expect("Failed to lock mock clock").monotonic_ms
+    }
+
+    fn subscribe(&self, waker: Weak<ClockWaker>) {
+        self.inner.lock().expect("Failed to lock mock clock").wakers.push(waker);
+    }
+}
+
+impl std::fmt::Debug for MockClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockClock").field("now_ms", &self.now_ms()).finish()
+    }
+}
+
+/// Gets the current time in milliseconds since UNIX epoch
+pub fn current_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_mock_clock_wakes_live_subscribers() {
+        let clock = MockClock::new(1_000);
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let waker: Arc<ClockWaker> = {
+            let wakes = wakes.clone();
+            Arc::new(move || {
+                wakes.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        clock.subscribe(Arc::downgrade(&waker));
+
+        clock.clone().advance(Duration::from_secs(2));
+        assert_eq!(clock.now_ms(), 3_000);
+        clock.set_ms(500);
+        assert_eq!(wakes.load(Ordering::SeqCst), 2);
+        assert_eq!(clock.monotonic_ms(), 2_000);
+
+        drop(waker);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(wakes.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,5 +1,3 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
 /// Errors that can occur during duration parsing
 #[derive(Debug)]
 pub enum ParseError {
@@ -69,11 +67,9 @@ pub fn parse_duration(duration_str: &str) -> Result<u64, ParseError> {
 }
 
 /// Gets the current time in milliseconds since UNIX epoch
+#[deprecated(note = "use utils::clock::current_time_ms or a Clock instead")]
 pub fn current_time_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64
+    super::clock::current_time_ms()
 }
 
 #[cfg(test)]
@@ -1,5 +1,9 @@
+pub mod clock;
 pub mod duration_parser;
 pub mod time;
 
-pub use duration_parser::{parse_duration, ParseError, current_time_ms};
-pub use time::current_time_ms as time_current_time_ms;
+pub use clock::{Clock, ClockJumpPolicy, ClockWaker, MockClock, SystemClock};
+#[allow(deprecated)]
+pub use duration_parser::current_time_ms;
+pub use duration_parser::{parse_duration, ParseError};
+pub use time::parse_timestamp;
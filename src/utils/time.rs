@@ -1,9 +1,77 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use super::ParseError;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 
 /// Gets the current time in milliseconds since UNIX epoch
+#[deprecated(note = "use utils::clock::current_time_ms or a Clock instead")]
 pub fn current_time_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64
+    super::clock::current_time_ms()
+}
+
+/// Parses a point in time and returns it in milliseconds since UNIX epoch
+///
+/// Supported formats:
+/// - "1718000000000" => milliseconds since UNIX epoch
+/// - "2024-06-10T14:02:00Z" => RFC 3339
+/// - "2024-06-10 14:02" or "2024-06-10 14:02:30" => local time
+/// - "14:02" or "14:02:30" => local time today
+pub fn parse_timestamp(timestamp_str: &str) -> Result<u64, ParseError> {
+    let timestamp_str = timestamp_str.trim();
+
+    if timestamp_str.is_empty() {
+        return Err(ParseError::InvalidFormat("Empty timestamp string".to_string()));
+    }
+
+    if timestamp_str.chars().all(|c| c.is_ascii_digit()) {
+        return timestamp_str
+            .parse()
+            .map_err(|_| ParseError::InvalidNumber(timestamp_str.to_string()));
+    }
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(timestamp_str) {
+        return to_epoch_ms(datetime.timestamp_millis(), timestamp_str);
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(timestamp_str, format).ok())
+        .or_else(|| {
+            ["%H:%M:%S", "%H:%M"]
+                .iter()
+                .find_map(|format| NaiveTime::parse_from_str(timestamp_str, format).ok())
+                .map(|time| NaiveDate::from(Local::now().naive_local()).and_time(time))
+        })
+        .ok_or_else(|| ParseError::InvalidFormat(timestamp_str.to_string()))?;
+
+    let local = Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| ParseError::InvalidFormat(format!("{} does not exist in local time", timestamp_str)))?;
+    to_epoch_ms(local.timestamp_millis(), timestamp_str)
+}
+
+fn to_epoch_ms(millis: i64, original: &str) -> Result<u64, ParseError> {
+    u64::try_from(millis)
+        .map_err(|_| ParseError::InvalidNumber(format!("{} is before the UNIX epoch", original)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_epoch_millis() {
+        assert_eq!(parse_timestamp("1718028120000").unwrap(), 1718028120000);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_timestamp("2024-06-10T14:02:00Z").unwrap(), 1718028120000);
+        assert_eq!(parse_timestamp("2024-06-10T16:02:00+02:00").unwrap(), 1718028120000);
+    }
+
+    #[test]
+    fn test_parse_invalid_timestamp() {
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("").is_err());
+    }
 }